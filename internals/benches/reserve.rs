@@ -0,0 +1,115 @@
+//! Compares encoding headers into a buffer pre-sized with
+//! `EncodableInHeader::encoded_size_hint` with encoding them into
+//! a buffer which has to grow while writing.
+//!
+//! A counting allocator is used to report the number of allocations
+//! per encoding (run with `--nocapture` to see them).
+//!
+//! Needs a nightly compiler: `cargo bench --features nightly`
+#![feature(test)]
+extern crate test;
+extern crate soft_ascii_string;
+extern crate mail_internals;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use test::Bencher;
+use soft_ascii_string::SoftAsciiStr;
+
+use mail_internals::MailType;
+use mail_internals::error::EncodingError;
+use mail_internals::encoder::{EncodingBuffer, EncodingWriter, EncodableInHeader};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const HEADER_NAME: &str = "X-Bench";
+const HEADER_COUNT: usize = 50;
+
+/// Words separated by FWS, knowing its exact encoded size.
+#[derive(Debug, Clone)]
+struct Words(&'static [&'static str]);
+
+impl EncodableInHeader for Words {
+    fn encode(&self, handle: &mut EncodingWriter) -> Result<(), EncodingError> {
+        for (idx, word) in self.0.iter().enumerate() {
+            if idx != 0 {
+                handle.write_fws();
+            }
+            handle.write_str(SoftAsciiStr::from_unchecked(word))?;
+        }
+        Ok(())
+    }
+
+    fn boxed_clone(&self) -> Box<EncodableInHeader> {
+        Box::new(self.clone())
+    }
+
+    fn encoded_size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.0.iter().map(|word| word.len()).sum::<usize>() + self.0.len() - 1;
+        (len, Some(len))
+    }
+}
+
+const WORDS: Words = Words(&[
+    "Lorem", "ipsum", "dolor", "sit", "amet,", "consetetur", "sadipscing",
+    "elitr,", "sed", "diam", "nonumy", "eirmod", "tempor", "invidunt"
+]);
+
+fn encode_headers(headers: &[Words], use_size_hint: bool) -> EncodingBuffer {
+    let mut encoder = EncodingBuffer::new(MailType::Ascii);
+    if use_size_hint {
+        // name + ": " + value + "\r\n"
+        let size = headers.iter()
+            .map(|header| HEADER_NAME.len() + 2 + header.encoded_size_hint().0 + 2)
+            .sum();
+        encoder.reserve(size);
+    }
+    for header in headers {
+        encoder.write_header_line(|handle| {
+            handle.write_header_name(SoftAsciiStr::from_unchecked(HEADER_NAME))?;
+            header.encode(handle)
+        }).unwrap();
+    }
+    encoder
+}
+
+fn bench_encode(bencher: &mut Bencher, use_size_hint: bool) {
+    let headers = vec![WORDS; HEADER_COUNT];
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    drop(encode_headers(&headers, use_size_hint));
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!("allocations per encoding (use_size_hint = {}): {}", use_size_hint, allocations);
+    bencher.iter(|| encode_headers(&headers, use_size_hint));
+}
+
+#[bench]
+fn encode_with_size_hint(bencher: &mut Bencher) {
+    bench_encode(bencher, true)
+}
+
+#[bench]
+fn encode_without_size_hint(bencher: &mut Bencher) {
+    bench_encode(bencher, false)
+}
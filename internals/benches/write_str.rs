@@ -0,0 +1,56 @@
+//! Compares writing header values in bulk (`write_str`) with writing
+//! them char by char (`write_char`), which is what `write_str` did before
+//! it started to copy clean us-ascii runs in one go.
+//!
+//! Needs a nightly compiler: `cargo bench --features nightly`
+#![feature(test)]
+extern crate test;
+extern crate soft_ascii_string;
+extern crate mail_internals;
+
+use test::Bencher;
+use soft_ascii_string::{SoftAsciiStr, SoftAsciiChar};
+
+use mail_internals::MailType;
+use mail_internals::encoder::{EncodingBuffer, EncodingWriter};
+
+const HEADER_COUNT: usize = 50;
+const WORDS: &[&str] = &[
+    "Lorem", "ipsum", "dolor", "sit", "amet,", "consetetur", "sadipscing",
+    "elitr,", "sed", "diam", "nonumy", "eirmod", "tempor", "invidunt"
+];
+
+fn encode_headers<FN>(write_word: FN) -> EncodingBuffer
+    where FN: Fn(&mut EncodingWriter, &SoftAsciiStr)
+{
+    let mut encoder = EncodingBuffer::new(MailType::Ascii);
+    for _ in 0..HEADER_COUNT {
+        encoder.write_header_line(|handle| {
+            handle.write_header_name(SoftAsciiStr::from_unchecked("X-Bench"))?;
+            for (idx, word) in WORDS.iter().enumerate() {
+                if idx != 0 {
+                    handle.write_fws();
+                }
+                write_word(handle, SoftAsciiStr::from_unchecked(word));
+            }
+            Ok(())
+        }).unwrap();
+    }
+    encoder
+}
+
+#[bench]
+fn write_str_bulk(bencher: &mut Bencher) {
+    bencher.iter(|| encode_headers(|handle, word| {
+        handle.write_str(word).unwrap();
+    }));
+}
+
+#[bench]
+fn write_char_wise(bencher: &mut Bencher) {
+    bencher.iter(|| encode_headers(|handle, word| {
+        for ch in word.as_str().chars() {
+            handle.write_char(SoftAsciiChar::from_unchecked(ch)).unwrap();
+        }
+    }));
+}
@@ -0,0 +1,170 @@
+//! Drives a `EncodingWriter` with random sequences of writes, FWS marks,
+//! (failing) transactions and finished/undone headers and checks that the
+//! output never contains a line longer then the hard line length limit
+//! or a orphan `'\r'`/`'\n'`, and that it is us-ascii if the mail is not
+//! internationalized (even if non us-ascii text is written with
+//! `write_str_unchecked`).
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate soft_ascii_string;
+extern crate mail_internals;
+
+use soft_ascii_string::{SoftAsciiStr, SoftAsciiChar};
+
+use mail_internals::MailType;
+use mail_internals::error::{EncodingError, EncodingErrorKind};
+use mail_internals::encoder::{EncodingBuffer, EncodingWriter, LINE_LEN_HARD_LIMIT};
+
+/// Nested transactions are limited to this depth.
+const MAX_DEPTH: usize = 4;
+
+struct Input<'a> {
+    data: &'a [u8]
+}
+
+impl<'a> Input<'a> {
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn byte(&mut self) -> u8 {
+        match self.data.split_first() {
+            Some((&first, rest)) => {
+                self.data = rest;
+                first
+            },
+            None => 0
+        }
+    }
+
+    /// Returns a text which is, depending on the next byte, repeated a lot.
+    fn text(&mut self) -> String {
+        let len = self.byte() as usize;
+        let repeat = match self.byte() {
+            0xff => 1 + self.byte() as usize * 64,
+            _ => 1
+        };
+        let len = std::cmp::min(len, self.data.len());
+        let (text, rest) = self.data.split_at(len);
+        self.data = rest;
+        String::from_utf8_lossy(text).repeat(repeat)
+    }
+
+    fn ascii_text(&mut self) -> String {
+        self.text().chars().filter(|ch| ch.is_ascii()).collect()
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut input = Input { data };
+    let mail_type = match input.byte() % 3 {
+        0 => MailType::Ascii,
+        1 => MailType::Mime8BitEnabled,
+        _ => MailType::Internationalized
+    };
+    let mut encoder = EncodingBuffer::new(mail_type);
+    {
+        let mut handle = encoder.writer();
+        while !input.is_empty() {
+            if run_op(&mut handle, &mut input, 0).is_err() {
+                handle.undo_header();
+            }
+        }
+        handle.finish_header();
+    }
+    check_output(encoder.as_slice());
+    if !mail_type.is_internationalized() {
+        assert!(encoder.as_slice().is_ascii(),
+            "non us-ascii output for {:?}: {:?}", mail_type, encoder.to_string_lossy());
+    }
+});
+
+fn run_op(handle: &mut EncodingWriter, input: &mut Input, depth: usize)
+    -> Result<(), EncodingError>
+{
+    match input.byte() % 11 {
+        0 => {
+            let text = input.ascii_text();
+            handle.write_str(SoftAsciiStr::from_unchecked(&text))
+        },
+        1 => {
+            handle.write_fws();
+            Ok(())
+        },
+        2 => {
+            handle.mark_fws_pos();
+            Ok(())
+        },
+        3 => {
+            let text = input.text();
+            handle.write_utf8(&text)
+        },
+        4 => {
+            let text = input.text();
+            handle.write_if_utf8(&text)
+                .handle_condition_failure(|_| Ok(()))
+        },
+        5 => {
+            let ops = input.byte();
+            if depth >= MAX_DEPTH {
+                return Ok(());
+            }
+            // runs the ops in a transaction which is rolled back if a op or,
+            // depending on `ops`, the transaction itself fails
+            handle.write_if("", |_| false).handle_condition_failure(|handle| {
+                for _ in 0..(ops % 8) {
+                    run_op(handle, input, depth + 1)?;
+                }
+                if ops & 0x80 != 0 {
+                    Err(EncodingErrorKind::Malformed.into())
+                } else {
+                    Ok(())
+                }
+            })
+        },
+        6 => {
+            if depth == 0 {
+                handle.undo_header();
+            }
+            Ok(())
+        },
+        7 => {
+            if depth == 0 {
+                handle.finish_header();
+            }
+            Ok(())
+        },
+        8 => {
+            let name = input.ascii_text();
+            if depth == 0 && !handle.has_unfinished_parts() {
+                handle.write_header_name(SoftAsciiStr::from_unchecked(&name))?;
+                // a value written after `write_header_name` must not start with WS
+                handle.write_char(SoftAsciiChar::from_unchecked('v'))?;
+            }
+            Ok(())
+        },
+        9 => {
+            let ch = (input.byte() & 0x7f) as char;
+            handle.write_char(SoftAsciiChar::from_unchecked(ch))
+        },
+        _ => {
+            let text = input.text();
+            handle.write_str_unchecked(&text)
+        }
+    }
+}
+
+fn check_output(out: &[u8]) {
+    let mut rest = out;
+    while let Some(idx) = rest.windows(2).position(|window| window == b"\r\n") {
+        let line = &rest[..idx];
+        assert!(line.len() <= LINE_LEN_HARD_LIMIT,
+            "line longer then the hard line length limit: {:?}", String::from_utf8_lossy(line));
+        assert!(!line.contains(&b'\r') && !line.contains(&b'\n'),
+            "orphan '\\r' or '\\n' in line: {:?}", String::from_utf8_lossy(line));
+        rest = &rest[idx + 2..];
+    }
+    assert!(rest.is_empty(), "output does not end with \"\\r\\n\": {:?}", String::from_utf8_lossy(out));
+}
@@ -64,8 +64,7 @@ fn _encoded_word_encode<O>( input: &str, out: &mut O )
 
     debug_assert!( USE_PADDING == true, "size calculation is tailored for padding");
 
-    let max_output_len = out.max_payload_len();
-    let max_input_len = calc_max_input_len(max_output_len);
+    let mut max_output_len = out.max_payload_len();
     let mut rest = input;
     let mut buff = String::with_capacity(max_output_len);
 
@@ -73,6 +72,7 @@ fn _encoded_word_encode<O>( input: &str, out: &mut O )
 
     loop {
         buff.clear();
+        let max_input_len = calc_max_input_len(max_output_len);
 
         // additional bytes in uf8 always start with binary b10xxxxxx
         let rest_len = rest.len();
@@ -108,6 +108,7 @@ fn _encoded_word_encode<O>( input: &str, out: &mut O )
             break
         } else {
             out.start_next_encoded_word();
+            max_output_len = out.max_payload_len();
         }
     }
     out.write_ecw_end();
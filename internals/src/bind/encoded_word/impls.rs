@@ -1,8 +1,12 @@
+use std::cmp;
+
 use soft_ascii_string::{ SoftAsciiString, SoftAsciiChar, SoftAsciiStr };
 
 use vec1::Vec1;
+use ::Charset;
+use ::error::EncodingError;
 use grammar::encoded_word::{ MAX_ECW_LEN, ECW_SEP_OVERHEAD };
-use ::encoder::EncodingWriter;
+use ::encoder::{EncodingWriter, LINE_LEN_HARD_LIMIT};
 use super::{ EncodedWordWriter, EncodedWordEncoding as Encoding };
 
 pub struct VecWriter<'a> {
@@ -51,24 +55,67 @@ impl<'a> EncodedWordWriter for VecWriter<'a> {
     }
 }
 
+/// A `EncodedWordWriter` writing directly to a `EncodingWriter`.
+///
+/// Soft line breaks are only possible at the separators between
+/// encoded words (as they are the only places marked as FWS), so
+/// an encoded word is never folded. If the first encoded word would
+/// not fit on the current line without breaching the hard line length
+/// limit it is made shorter, so this only fails if there is not even
+/// room for a single char in a encoded word left on the line.
+///
+/// As `EncodedWordWriter` methods can not fail the first error is
+/// kept and all writes after it are skipped, use `finish` to get it.
 pub struct WriterWrapper<'a, 'b: 'a>{
     charset: &'a SoftAsciiStr,
     encoding: Encoding,
-    encoder_handle: &'a mut EncodingWriter<'b>
+    encoder_handle: &'a mut EncodingWriter<'b>,
+    first_word: bool,
+    error: Option<EncodingError>
 }
 
+/// The smallest payload length used for a shortened first encoded word,
+/// which is enough for a 4 byte utf-8 char in both encodings.
+const MIN_FIRST_PAYLOAD_LEN: usize = 12;
+
 impl<'a, 'b: 'a> WriterWrapper<'a, 'b> {
     pub fn new_with_charset(charset: &'a SoftAsciiStr,
                             encoding: Encoding,
                             encoder: &'a mut EncodingWriter<'b> ) -> Self
     {
-        WriterWrapper { charset, encoding, encoder_handle: encoder }
+        WriterWrapper { charset, encoding, encoder_handle: encoder, first_word: true, error: None }
     }
 
+    /// Creates a new writer wrapper using the canonical `utf-8` charset label.
     pub fn new(encoding: Encoding,
                encoder: &'a mut EncodingWriter<'b> ) -> Self
     {
-        Self::new_with_charset(SoftAsciiStr::from_unchecked("utf8"), encoding, encoder)
+        //UNWRAP_SAFE: Utf8 is not Other
+        let label = Charset::Utf8.static_label().unwrap();
+        Self::new_with_charset(SoftAsciiStr::from_unchecked(label), encoding, encoder)
+    }
+
+    /// Returns the first error which happened while writing (if any).
+    pub fn error(&self) -> Option<&EncodingError> {
+        self.error.as_ref()
+    }
+
+    /// Returns the first error which happened while writing (if any).
+    ///
+    /// If it returns an error the written encoded words are incomplete,
+    /// so the header should be discarded (e.g. using `undo_header`).
+    #[must_use]
+    pub fn finish(self) -> Result<(), EncodingError> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(())
+        }
+    }
+
+    fn track_result(&mut self, res: Result<(), EncodingError>) {
+        if let Err(err) = res {
+            self.error = Some(err);
+        }
     }
 }
 
@@ -79,20 +126,129 @@ impl<'a, 'b: 'a> EncodedWordWriter for WriterWrapper<'a, 'b> {
     }
 
     fn write_charset( &mut self ) {
-        //TODO fix
-        let _ = self.encoder_handle.write_str( self.charset );
+        if self.error.is_none() {
+            let res = self.encoder_handle.write_str( self.charset );
+            self.track_result(res);
+        }
     }
 
     fn write_ecw_seperator( &mut self ) {
-        self.encoder_handle.write_fws();
+        self.first_word = false;
+        if self.error.is_none() {
+            self.encoder_handle.write_fws();
+        }
     }
 
     fn write_char( &mut self, ch: SoftAsciiChar ) {
-        //TODO fix
-        let _ = self.encoder_handle.write_char( ch );
+        if self.error.is_none() {
+            let res = self.encoder_handle.write_char( ch );
+            self.track_result(res);
+        }
     }
 
     fn max_payload_len( &self ) -> usize {
-        MAX_ECW_LEN - ECW_SEP_OVERHEAD - self.charset.len() - 1
+        let overhead = ECW_SEP_OVERHEAD + self.charset.len() + 1;
+        let max_len = MAX_ECW_LEN - overhead;
+        if !self.first_word {
+            return max_len;
+        }
+        // there is no FWS before the first word so it has to fit on the current line
+        let line_len = self.encoder_handle.current_line_byte_length() + overhead;
+        let room = LINE_LEN_HARD_LIMIT.saturating_sub(line_len);
+        if room < MIN_FIRST_PAYLOAD_LEN {
+            // writing will fail with `HardLineLengthLimitBreached` anyway
+            max_len
+        } else {
+            cmp::min(max_len, room)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use soft_ascii_string::SoftAsciiStr;
+
+    use ::MailType;
+    use ::error::EncodingErrorKind;
+    use ::encoder::{EncodingBuffer, LINE_LEN_HARD_LIMIT};
+    use grammar::encoded_word::EncodedWordContext;
+    use super::super::EncodedWordEncoding;
+    use super::WriterWrapper;
+
+    #[test]
+    fn encoded_words_are_never_folded() {
+        let mut encoder = EncodingBuffer::new(MailType::Ascii);
+        {
+            let mut handle = encoder.writer();
+            assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked(
+                "Subject: a_line_which_already_is_close_to_the_soft_line_length_limit")));
+            handle.write_fws();
+            {
+                let mut writer = WriterWrapper::new(EncodedWordEncoding::Base64, &mut handle);
+                EncodedWordEncoding::Base64.encode(
+                    "↓".repeat(60), EncodedWordContext::Text, &mut writer);
+                assert_ok!(writer.finish());
+            }
+            handle.finish_header();
+        }
+        let out = encoder.as_str().unwrap();
+        let lines = out.split("\r\n").filter(|line| !line.is_empty()).collect::<Vec<_>>();
+        assert!(lines.len() > 2, "{:?}", out);
+        for line in lines.iter().skip(1) {
+            // every continuation line contains only complete encoded words
+            for word in line.split(' ').filter(|word| !word.is_empty()) {
+                assert!(word.starts_with("=?utf-8?B?") && word.ends_with("?="), "{:?}", out);
+            }
+        }
+    }
+
+    #[test]
+    fn first_word_is_shortened_near_the_hard_limit() {
+        for &encoding in &[EncodedWordEncoding::Base64, EncodedWordEncoding::QuotedPrintable] {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            let prefix = format!("X-Long: {}", "X".repeat(932));
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked(&prefix)));
+                {
+                    let mut writer = WriterWrapper::new(encoding, &mut handle);
+                    encoding.encode("↓".repeat(30), EncodedWordContext::Text, &mut writer);
+                    assert_ok!(writer.finish());
+                }
+                handle.finish_header();
+            }
+            let out = encoder.as_str().unwrap();
+            assert!(out.starts_with(&*prefix), "{:?}", out);
+            let lines = out.split("\r\n").filter(|line| !line.is_empty()).collect::<Vec<_>>();
+            assert!(lines.len() > 1, "{:?}", out);
+            for (idx, line) in lines.iter().enumerate() {
+                assert!(line.len() <= LINE_LEN_HARD_LIMIT, "{:?}", out);
+                let words = if idx == 0 { &line[prefix.len()..] } else { &line[..] };
+                for word in words.split(' ').filter(|word| !word.is_empty()) {
+                    assert!(word.starts_with("=?utf-8?") && word.ends_with("?="), "{:?}", out);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn errors_near_the_hard_limit_are_not_ignored() {
+        let mut encoder = EncodingBuffer::new(MailType::Ascii);
+        {
+            let mut handle = encoder.writer();
+            let prefix = format!("X-Long: {}", "X".repeat(985));
+            assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked(&prefix)));
+            let res = {
+                let mut writer = WriterWrapper::new(EncodedWordEncoding::Base64, &mut handle);
+                EncodedWordEncoding::Base64.encode("↓", EncodedWordContext::Text, &mut writer);
+                assert!(writer.error().is_some());
+                writer.finish()
+            };
+            let err = assert_err!(res);
+            assert_eq!(err.kind(), EncodingErrorKind::HardLineLengthLimitBreached);
+            handle.undo_header();
+        }
+        assert_eq!(encoder.as_slice(), b"");
     }
 }
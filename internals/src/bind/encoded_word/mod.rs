@@ -1,5 +1,7 @@
 use soft_ascii_string::{ SoftAsciiStr, SoftAsciiChar };
 
+use grammar::encoded_word::EncodedWordContext;
+
 use super::{base64, quoted_printable};
 
 mod impls;
@@ -31,7 +33,10 @@ impl EncodedWordEncoding {
     /// As both algorithm need to know about code point boundaries
     /// only encoding utf8 is supported for now
     ///
-    pub fn encode<R, O>(&self, input: R, out: &mut O)
+    /// The context is the place the encoded word will appear in, it
+    /// determines which chars have to be quoted with quoted printable.
+    /// Base64 is valid in all contexts.
+    pub fn encode<R, O>(&self, input: R, ctx: EncodedWordContext, out: &mut O)
         where R: AsRef<str>, O: EncodedWordWriter
     {
         use self::EncodedWordEncoding::*;
@@ -41,7 +46,7 @@ impl EncodedWordEncoding {
                 base64::encoded_word_encode(input, out)
             },
             QuotedPrintable => {
-                quoted_printable::encoded_word_encode_utf8(input, out)
+                quoted_printable::encoded_word_encode_utf8(input, ctx, out)
             }
         }
     }
@@ -55,10 +60,12 @@ pub trait EncodedWordWriter {
 
     /// Returns the maximal length of the paylod/encoded data
     ///
-    /// Any number of calls to methods on in trait in any way
-    /// should never be able to change the returned value.
-    /// Only changing e.g. the charset or encoding should be
-    /// able to change what `max_paylod_len` returns.
+    /// It is called before the first encoded word is started and after
+    /// each call to `start_next_encoded_word`. The value returned for the
+    /// first encoded word can be smaller then the one for the following
+    /// words (e.g. if there is not enough room left on the current line),
+    /// but the value returned for the following words should only depend
+    /// on e.g. the charset and encoding.
     fn max_payload_len( &self ) -> usize;
 
     fn write_ecw_start( &mut self ) {
@@ -1,14 +1,24 @@
 use std::borrow::Cow;
+use std::convert::TryInto;
 
 use soft_ascii_string::{ SoftAsciiStr, SoftAsciiString};
-use grammar::is_token_char;
+use quoted_string;
 use percent_encoding::{
     EncodeSet,
     percent_encode
 };
 
+use ::{MailType, Charset, TokenStr};
+use ::error::EncodingError;
+use grammar::{is_token, is_token_char, is_ascii_vchar, is_ws};
+use super::quoted_string::{MailQsSpec, InternationalizedMailQsSpec};
+
+/// The set of bytes which have to be percent encoded in a RFC 2231 parameter value.
+///
+/// This are all bytes which are not token chars (which includes all
+/// non us-ascii bytes).
 #[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
-struct MimeParamEncodingSet;
+pub struct MimeParamEncodingSet;
 impl EncodeSet for MimeParamEncodingSet {
     fn contains(&self, byte: u8) -> bool {
         //if it is in the encoding set we need to encode it
@@ -17,16 +27,32 @@ impl EncodeSet for MimeParamEncodingSet {
     }
 }
 
+/// The set of bytes which have to be percent encoded in a RFC 2231 extended parameter value.
+///
+/// This are all bytes which are not `attribute-char`s, i.e. all bytes
+/// in `MimeParamEncodingSet` and additionally `'*'`, `'\''` and `'%'`
+/// (which are token chars but have a special meaning in extended values).
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
+pub struct Rfc2231AttrCharEncodingSet;
+impl EncodeSet for Rfc2231AttrCharEncodingSet {
+    fn contains(&self, byte: u8) -> bool {
+        match byte {
+            b'*' | b'\'' | b'%' => true,
+            _ => MimeParamEncodingSet.contains(byte)
+        }
+    }
+}
+
 
 /// percent encodes a byte sequence so that it can be used
 /// in a RFC 2231 conform encoded mime header parameter
 pub fn percent_encode_param_value<'a, R>(input: &'a R) -> Cow<'a, SoftAsciiStr>
     where R: ?Sized+AsRef<[u8]>
 {
-    let cow: Cow<'a, str> = percent_encode(input.as_ref(), MimeParamEncodingSet).into();
+    let cow: Cow<'a, str> = percent_encode(input.as_ref(), Rfc2231AttrCharEncodingSet).into();
     match cow {
         Cow::Owned(o) =>
-            //SAFE: MimeParamEncodingSet makes all non-us-ascii bytes encoded AND
+            //SAFE: Rfc2231AttrCharEncodingSet makes all non-us-ascii bytes encoded AND
             // percent_encoding::percent_encode always only produces ascii anyway
             Cow::Owned(SoftAsciiString::from_unchecked(o)),
         Cow::Borrowed(b) =>
@@ -34,6 +60,223 @@ pub fn percent_encode_param_value<'a, R>(input: &'a R) -> Cow<'a, SoftAsciiStr>
     }
 }
 
+/// percent encodes arbitrary bytes appending the result to `out`
+///
+/// Unlike `percent_encode_param_value` this does not create an
+/// intermediate buffer. It works with any input, the bytes do not
+/// need to be valid utf-8 (e.g. file names from a non utf-8 file system).
+pub fn percent_encode_bytes<S>(bytes: &[u8], set: &S, out: &mut String)
+    where S: EncodeSet
+{
+    for chunk in percent_encode(bytes, set.clone()) {
+        out.push_str(chunk);
+    }
+}
+
+/// Specifies how a mime parameter value which is not a token is represented.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ParamEncodingPolicy {
+    /// Use a quoted-string and only fall back to the RFC 2231
+    /// extended notation (`name*=utf-8''...`) if quoting is not
+    /// possible with the given mail type.
+    PreferQuoted,
+
+    /// Always use the RFC 2231 extended notation.
+    PreferExtended,
+
+    /// Emit both a plain `name=` parameter and a `name*=` parameter.
+    ///
+    /// The plain parameter comes first and is ascii only, which means
+    /// it is lossy if the value contains non us-ascii characters: each
+    /// char which can not appear in a us-ascii quoted-string is replaced
+    /// by a single `_`, e.g. `"tüxt.txt"` becomes
+    /// `filename="t_xt.txt"; filename*=utf-8''t%C3%BCxt.txt`.
+    /// This is what RFC 6266 recommends for e.g. the `filename` parameter.
+    Both
+}
+
+impl Default for ParamEncodingPolicy {
+    fn default() -> Self {
+        ParamEncodingPolicy::PreferQuoted
+    }
+}
+
+/// Options for `create_encoded_mime_parameter_with_options`.
+///
+/// New options can be added in any release, so outside of this
+/// crate it has to be created through `new`/`default` and the
+/// `with_*` methods.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Default)]
+#[non_exhaustive]
+pub struct ParamEncodingOptions {
+    /// How values which are not tokens are represented.
+    pub policy: ParamEncodingPolicy,
+
+    /// Use the (non canonical) `utf8` label instead of `utf-8` in
+    /// extended parameters.
+    ///
+    /// This exists for compatibility with older versions which
+    /// always used `utf8''`.
+    pub legacy_utf8_label: bool
+}
+
+impl ParamEncodingOptions {
+
+    /// Creates the default options.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns self with the given policy.
+    pub fn with_policy(mut self, policy: ParamEncodingPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Returns self with the given `legacy_utf8_label` setting.
+    pub fn with_legacy_utf8_label(mut self, legacy_utf8_label: bool) -> Self {
+        self.legacy_utf8_label = legacy_utf8_label;
+        self
+    }
+}
+
+/// A mime parameter in the form it should be written to a mail.
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct EncodedMimeParameter {
+    /// The parameter name, including a trailing `*` if it's
+    /// an extended parameter.
+    pub name: SoftAsciiString,
+    /// The parameter value, already quoted/percent encoded.
+    ///
+    /// This is only non us-ascii if the mail type is internationalized.
+    pub value: String
+}
+
+/// Creates the parameter(s) needed to represent `name=value` in a mime header.
+///
+/// If the value is a token it is used as is independent of the `policy`,
+/// else the `policy` decides how it is represented (see `ParamEncodingPolicy`).
+///
+/// The `name` can be a `&str` or a pre-validated `&TokenStr`/`&Token`
+/// (e.g. created with `token!`), in which case it's not checked again.
+///
+/// # Error
+///
+/// fails if `name` is not a token
+pub fn create_encoded_mime_parameter<'n, N>(
+    name: N,
+    value: &str,
+    mail_type: MailType,
+    policy: ParamEncodingPolicy
+) -> Result<Vec<EncodedMimeParameter>, EncodingError>
+    where N: TryInto<&'n TokenStr>, EncodingError: From<N::Error>
+{
+    let options = ParamEncodingOptions { policy, ..Default::default() };
+    create_encoded_mime_parameter_with_options(name, value, mail_type, options)
+}
+
+/// Like `create_encoded_mime_parameter` but allows specifying additional options.
+pub fn create_encoded_mime_parameter_with_options<'n, N>(
+    name: N,
+    value: &str,
+    mail_type: MailType,
+    options: ParamEncodingOptions
+) -> Result<Vec<EncodedMimeParameter>, EncodingError>
+    where N: TryInto<&'n TokenStr>, EncodingError: From<N::Error>
+{
+    let name: &TokenStr = name.try_into()
+        .map_err(|err| EncodingError::from(err).with_mail_type_or_else(|| Some(mail_type)))?;
+    let name = name.as_soft_str();
+
+    if is_token(value) {
+        return Ok(vec![ plain_param(name, value.to_owned()) ]);
+    }
+
+    let charset = utf8_charset_label(options.legacy_utf8_label);
+    use self::ParamEncodingPolicy::*;
+    let params = match options.policy {
+        PreferQuoted => {
+            if let Some(quoted) = quote_param_value(value, mail_type) {
+                vec![ plain_param(name, quoted) ]
+            } else {
+                vec![ extended_param(name, value.as_bytes(), charset) ]
+            }
+        },
+        PreferExtended => {
+            vec![ extended_param(name, value.as_bytes(), charset) ]
+        },
+        Both => {
+            let fallback = lossy_ascii_param_value(value);
+            let quoted = quote_param_value(&fallback, MailType::Ascii)
+                .expect("[BUG] quoting printable us-ascii can not fail");
+            vec![
+                plain_param(name, quoted),
+                extended_param(name, value.as_bytes(), charset)
+            ]
+        }
+    };
+    Ok(params)
+}
+
+fn plain_param(name: &SoftAsciiStr, value: String) -> EncodedMimeParameter {
+    EncodedMimeParameter { name: name.to_owned(), value }
+}
+
+/// Creates a RFC 2231 extended parameter (`name*=charset''value`) for arbitrary bytes.
+///
+/// The `charset` describes the encoding of the bytes. If it's
+/// not known `Charset::Unknown8Bit` (RFC 1428) can be used.
+///
+/// # Error
+///
+/// fails if `name` is not a token
+pub fn create_extended_mime_parameter<'n, N>(name: N, value: &[u8], charset: &Charset)
+    -> Result<EncodedMimeParameter, EncodingError>
+    where N: TryInto<&'n TokenStr>, EncodingError: From<N::Error>
+{
+    let name: &TokenStr = name.try_into()?;
+    let name = name.as_soft_str();
+    Ok(extended_param(name, value, charset.as_str()))
+}
+
+fn utf8_charset_label(legacy: bool) -> &'static str {
+    if legacy {
+        "utf8"
+    } else {
+        //UNWRAP_SAFE: Utf8 is not Other
+        Charset::Utf8.static_label().unwrap()
+    }
+}
+
+fn extended_param(name: &SoftAsciiStr, value: &[u8], charset: &str)
+    -> EncodedMimeParameter
+{
+    let mut ext_name = name.to_owned();
+    ext_name.push_str(SoftAsciiStr::from_unchecked("*"));
+    let mut ext_value = String::with_capacity(charset.len() + 2 + value.len());
+    ext_value.push_str(charset);
+    ext_value.push_str("''");
+    percent_encode_bytes(value, &Rfc2231AttrCharEncodingSet, &mut ext_value);
+    EncodedMimeParameter { name: ext_name, value: ext_value }
+}
+
+fn quote_param_value(value: &str, mail_type: MailType) -> Option<String> {
+    let res = if mail_type.is_internationalized() {
+        quoted_string::quote::<InternationalizedMailQsSpec>(value)
+    } else {
+        quoted_string::quote::<MailQsSpec>(value)
+    };
+    res.ok()
+}
+
+/// replaces all chars which can not appear in an us-ascii quoted-string with `_`
+fn lossy_ascii_param_value(value: &str) -> String {
+    value.chars()
+        .map(|ch| if is_ascii_vchar(ch) || is_ws(ch) { ch } else { '_' })
+        .collect()
+}
+
 
 #[cfg(test)]
 mod test {
@@ -53,4 +296,196 @@ mod test {
         let res = percent_encode_param_value(input);
         assert_eq!(res, Cow::Borrowed(input));
     }
-}
\ No newline at end of file
+
+    fn to_pairs(params: Vec<EncodedMimeParameter>) -> Vec<(String, String)> {
+        params.into_iter()
+            .map(|param| (param.name.as_str().to_owned(), param.value))
+            .collect()
+    }
+
+    #[test]
+    fn token_values_are_not_affected_by_policy() {
+        use self::ParamEncodingPolicy::*;
+        for &policy in &[PreferQuoted, PreferExtended, Both] {
+            let params = assert_ok!(create_encoded_mime_parameter(
+                "charset", "utf-8", MailType::Ascii, policy));
+            assert_eq!(to_pairs(params), vec![
+                ("charset".to_owned(), "utf-8".to_owned())
+            ]);
+        }
+    }
+
+    #[test]
+    fn prefer_quoted_quotes_if_possible() {
+        let params = assert_ok!(create_encoded_mime_parameter(
+            "filename", "a b.txt", MailType::Ascii, ParamEncodingPolicy::PreferQuoted));
+        assert_eq!(to_pairs(params), vec![
+            ("filename".to_owned(), "\"a b.txt\"".to_owned())
+        ]);
+    }
+
+    #[test]
+    fn prefer_quoted_falls_back_to_extended() {
+        let params = assert_ok!(create_encoded_mime_parameter(
+            "filename", "tüxt.txt", MailType::Ascii, ParamEncodingPolicy::PreferQuoted));
+        assert_eq!(to_pairs(params), vec![
+            ("filename*".to_owned(), "utf-8''t%C3%BCxt.txt".to_owned())
+        ]);
+    }
+
+    #[test]
+    fn prefer_quoted_uses_utf8_if_internationalized() {
+        let params = assert_ok!(create_encoded_mime_parameter(
+            "filename", "tüxt.txt", MailType::Internationalized,
+            ParamEncodingPolicy::PreferQuoted));
+        assert_eq!(to_pairs(params), vec![
+            ("filename".to_owned(), "\"tüxt.txt\"".to_owned())
+        ]);
+    }
+
+    #[test]
+    fn prefer_extended() {
+        let params = assert_ok!(create_encoded_mime_parameter(
+            "filename", "a b.txt", MailType::Internationalized,
+            ParamEncodingPolicy::PreferExtended));
+        assert_eq!(to_pairs(params), vec![
+            ("filename*".to_owned(), "utf-8''a%20b.txt".to_owned())
+        ]);
+    }
+
+    #[test]
+    fn both_emits_plain_first() {
+        let params = assert_ok!(create_encoded_mime_parameter(
+            "filename", "tüxt.txt", MailType::Internationalized, ParamEncodingPolicy::Both));
+        assert_eq!(to_pairs(params), vec![
+            ("filename".to_owned(), "\"t_xt.txt\"".to_owned()),
+            ("filename*".to_owned(), "utf-8''t%C3%BCxt.txt".to_owned())
+        ]);
+    }
+
+    #[test]
+    fn both_replaces_each_non_ascii_char_in_plain_value() {
+        let params = assert_ok!(create_encoded_mime_parameter(
+            "filename", "ä↓ b😀.txt", MailType::Ascii, ParamEncodingPolicy::Both));
+        assert_eq!(to_pairs(params), vec![
+            ("filename".to_owned(), "\"__ b_.txt\"".to_owned()),
+            ("filename*".to_owned(), "utf-8''%C3%A4%E2%86%93%20b%F0%9F%98%80.txt".to_owned())
+        ]);
+    }
+
+    #[test]
+    fn extended_values_escape_special_attribute_chars() {
+        let params = assert_ok!(create_encoded_mime_parameter(
+            "filename", "50% *'off'.txt", MailType::Ascii, ParamEncodingPolicy::PreferExtended));
+        assert_eq!(to_pairs(params), vec![
+            ("filename*".to_owned(), "utf-8''50%25%20%2A%27off%27.txt".to_owned())
+        ]);
+
+        let params = assert_ok!(create_encoded_mime_parameter(
+            "filename", "50% *'off'.txt", MailType::Ascii, ParamEncodingPolicy::Both));
+        assert_eq!(to_pairs(params), vec![
+            ("filename".to_owned(), "\"50% *'off'.txt\"".to_owned()),
+            ("filename*".to_owned(), "utf-8''50%25%20%2A%27off%27.txt".to_owned())
+        ]);
+    }
+
+    #[test]
+    fn percent_encode_non_utf8_bytes() {
+        let mut out = String::new();
+        percent_encode_bytes(b"caf\xE9 \xFF\x00.txt", &Rfc2231AttrCharEncodingSet, &mut out);
+        assert_eq!(out, "caf%E9%20%FF%00.txt");
+    }
+
+    #[test]
+    fn percent_encode_special_attribute_chars() {
+        let mut out = String::new();
+        percent_encode_bytes(b"100%*'.txt", &Rfc2231AttrCharEncodingSet, &mut out);
+        assert_eq!(out, "100%25%2A%27.txt");
+        assert_eq!(percent_encode_param_value("50%").as_str(), "50%25");
+    }
+
+    #[test]
+    fn percent_encode_appends() {
+        let mut out = String::from("utf8''");
+        percent_encode_bytes(b"a b", &Rfc2231AttrCharEncodingSet, &mut out);
+        assert_eq!(out, "utf8''a%20b");
+    }
+
+    #[test]
+    fn percent_encode_random_bytes() {
+        use percent_encoding::percent_decode;
+
+        // simple deterministic pseudo random generator (LCG), as we
+        // don't need any cryptographic properties here
+        let mut state = 0x2545_F491_u32;
+        let mut next_byte = || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8
+        };
+
+        for len in 0..256 {
+            let input: Vec<u8> = (0..len).map(|_| next_byte()).collect();
+            let mut out = String::new();
+            percent_encode_bytes(&input, &Rfc2231AttrCharEncodingSet, &mut out);
+            assert!(out.bytes().all(|b| b == b'%' || !Rfc2231AttrCharEncodingSet.contains(b)),
+                "{:?}", out);
+            let decoded: Vec<u8> = percent_decode(out.as_bytes()).collect();
+            assert_eq!(decoded, input);
+        }
+    }
+
+    #[test]
+    fn extended_parameter_from_bytes() {
+        let param = assert_ok!(create_extended_mime_parameter(
+            "filename", b"caf\xE9.txt", &Charset::Iso8859_1));
+        assert_eq!(param.name.as_str(), "filename*");
+        assert_eq!(param.value, "iso-8859-1''caf%E9.txt");
+    }
+
+    #[test]
+    fn extended_parameter_uses_canonical_label() {
+        let charset = assert_ok!(Charset::parse("LATIN1"));
+        let param = assert_ok!(create_extended_mime_parameter("filename", b"x", &charset));
+        assert_eq!(param.value, "iso-8859-1''x");
+    }
+
+    #[test]
+    fn extended_parameter_name_has_to_be_a_token() {
+        let err = assert_err!(create_extended_mime_parameter("file name", b"x", &Charset::Utf8));
+        assert_eq!(err.input_offset(), Some(4));
+    }
+
+    #[test]
+    fn legacy_utf8_label() {
+        let options = ParamEncodingOptions {
+            policy: ParamEncodingPolicy::PreferExtended,
+            legacy_utf8_label: true
+        };
+        let params = assert_ok!(create_encoded_mime_parameter_with_options(
+            "filename", "a b.txt", MailType::Ascii, options));
+        assert_eq!(to_pairs(params), vec![
+            ("filename*".to_owned(), "utf8''a%20b.txt".to_owned())
+        ]);
+    }
+
+    #[test]
+    fn name_has_to_be_a_token() {
+        let err = assert_err!(create_encoded_mime_parameter(
+            "file name", "x", MailType::Ascii, ParamEncodingPolicy::PreferQuoted));
+        assert_eq!(err.mail_type(), Some(MailType::Ascii));
+        assert_eq!(err.input_offset(), Some(4));
+    }
+
+    #[test]
+    fn pre_validated_names() {
+        let params = assert_ok!(create_encoded_mime_parameter(
+            token!("filename"), "a b", MailType::Ascii, ParamEncodingPolicy::PreferQuoted));
+        assert_eq!(to_pairs(params), vec![
+            ("filename".to_owned(), "\"a b\"".to_owned())
+        ]);
+
+        let name = assert_ok!(::Token::new("filename"));
+        let param = assert_ok!(create_extended_mime_parameter(&name, b"x", &Charset::Utf8));
+        assert_eq!(param.name.as_str(), "filename*");
+    }
+}
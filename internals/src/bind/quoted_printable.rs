@@ -3,6 +3,7 @@ use { quoted_printable as extern_quoted_printable };
 
 use failure::Fail;
 use ::error::{EncodingError, EncodingErrorKind};
+use grammar::encoded_word::EncodedWordContext;
 use super::encoded_word::EncodedWordWriter;
 
 /// a quoted printable encoding suitable for content transfer encoding,
@@ -28,24 +29,35 @@ pub fn normal_decode<R: AsRef<[u8]>>(input: R)
 
 /// a quoted printable decoding suitable for decoding a quoted printable
 /// encpded text in encoded words
-#[inline(always)]
+///
+/// This is the standard decoding, except that `'_'` is decoded
+/// as a space (`' '`) as required by RFC 2047.
 pub fn encoded_word_decode<R: AsRef<[u8]>>( input: R ) -> Result<Vec<u8>, EncodingError> {
-    //we can just use the stadard decoding
-    normal_decode( input )
+    let input = input.as_ref();
+    if !input.contains(&b'_') {
+        return normal_decode(input);
+    }
+    // we replace it with it's encoded form instead of a ' ', as
+    // the decoder might strip trailing white space
+    let mut replaced = Vec::with_capacity(input.len() + 4);
+    for &byte in input {
+        if byte == b'_' {
+            replaced.extend_from_slice(b"=20");
+        } else {
+            replaced.push(byte);
+        }
+    }
+    normal_decode(replaced)
 }
 
-//FIXME we don't use EncodedWord context here,
-// instead we use the most restructive context as a basis,
-// making it compatilble with all context, but not nessesary
-// the best solution...
 /// Simple wrapper around ecoded_word_encode for utf8 strings only
-pub fn encoded_word_encode_utf8<'a,  O>(word: &str, writer: &mut O )
+pub fn encoded_word_encode_utf8<'a,  O>(word: &str, ctx: EncodedWordContext, writer: &mut O )
     where O: EncodedWordWriter
 {
     let iter = word.char_indices().map( |(idx, ch)| {
         &word.as_bytes()[idx..idx+ch.len_utf8()]
     });
-    encoded_word_encode(iter, writer );
+    encoded_word_encode(iter, ctx, writer );
 }
 
 ///
@@ -69,10 +81,16 @@ pub fn encoded_word_encode_utf8<'a,  O>(word: &str, writer: &mut O )
 /// Note that a chunk can with more than 21 byte is not guranteed to
 /// work, and can trigger a panic.
 ///
-/// As this has to be safe for usage in all header contexts, additional
-/// to the chars required by the standard (i.e. '=') following chars are ALWAYS
-/// quoted' ', '\t', '?', '(', ')'. Also '\n','\r' see the note below for more
-/// details.
+/// Which chars are quoted depends on the context the encoded word
+/// will appear in (RFC 2047 §5):
+///
+/// - in all contexts `'='`, `'?'`, `'_'` and all non printable/non us-ascii
+///   bytes are quoted, `' '` is written as `'_'`
+/// - in a `Comment` additionally `'('`, `')'`, `'"'` and `'\\'` are quoted
+/// - in a `Phrase` only letters, digits and `'!'`, `'*'`, `'+'`, `'-'`, `'/'`
+///   are not quoted
+///
+/// Also '\n','\r' are always quoted, see the note below for more details.
 ///
 ///
 /// # Panics:
@@ -100,12 +118,11 @@ pub fn encoded_word_encode_utf8<'a,  O>(word: &str, writer: &mut O )
 ///   might be encoded with completely different bytes, but when the RFC speaks of
 ///   '\r','\n' it normally means the bytes 10/13 independent of the character set,
 ///   or if they appear in a image, zip-archiev etc. )
-pub fn encoded_word_encode<'a, I, O>(input: I, out: &mut O )
+pub fn encoded_word_encode<'a, I, O>(input: I, ctx: EncodedWordContext, out: &mut O )
     where I: Iterator<Item=&'a [u8]>, O: EncodedWordWriter
 {
+    let mut remaining = out.max_payload_len();
     out.write_ecw_start();
-    let max_payload_len = out.max_payload_len();
-    let mut remaining = max_payload_len;
     //WARN: on remaining being > 67
     let mut buf = [SoftAsciiChar::from_unchecked('X'); 16];
 
@@ -114,30 +131,22 @@ pub fn encoded_word_encode<'a, I, O>(input: I, out: &mut O )
 
         for byte in chunk {
             let byte = *byte;
-            match byte {
-                // this is the way to go as long as we don't want to behave differently for
-                // different context, the COMMENT context allows more chars, and the
-                // TEXT context even more
-                b'!' | b'*' |
-                b'+' | b'-' |
-                b'/' | b'_' |
-                b'0'...b'9' |
-                b'A'...b'Z' |
-                b'a'...b'z'  => {
-                    buf[buf_idx] = SoftAsciiChar::from_unchecked(byte as char);
-                    buf_idx += 1;
-                },
-                _otherwise => {
-                    buf[buf_idx] = SoftAsciiChar::from_unchecked('=');
-                    buf[buf_idx+1] = lower_nibble_to_hex( byte >> 4 );
-                    buf[buf_idx+2] = lower_nibble_to_hex( byte );
-                    buf_idx += 3;
-                }
+            if byte == b' ' {
+                buf[buf_idx] = SoftAsciiChar::from_unchecked('_');
+                buf_idx += 1;
+            } else if can_be_written_unquoted(byte, ctx) {
+                buf[buf_idx] = SoftAsciiChar::from_unchecked(byte as char);
+                buf_idx += 1;
+            } else {
+                buf[buf_idx] = SoftAsciiChar::from_unchecked('=');
+                buf[buf_idx+1] = lower_nibble_to_hex( byte >> 4 );
+                buf[buf_idx+2] = lower_nibble_to_hex( byte );
+                buf_idx += 3;
             }
         }
         if buf_idx > remaining {
             out.start_next_encoded_word();
-            remaining = max_payload_len;
+            remaining = out.max_payload_len();
         }
         if buf_idx > remaining {
             panic!( "single character longer then max length ({:?}) of encoded word", remaining );
@@ -150,6 +159,30 @@ pub fn encoded_word_encode<'a, I, O>(input: I, out: &mut O )
     out.write_ecw_end()
 }
 
+/// True if the byte can appear unquoted in a q-encoded word in the given context.
+fn can_be_written_unquoted(byte: u8, ctx: EncodedWordContext) -> bool {
+    match byte {
+        b'!' | b'*' |
+        b'+' | b'-' |
+        b'/' |
+        b'0'...b'9' |
+        b'A'...b'Z' |
+        b'a'...b'z' => true,
+        // these have special meaning in the encoded text
+        b'=' | b'?' | b'_' => false,
+        // any other printable us-ascii char
+        b'!'...b'~' => match ctx {
+            EncodedWordContext::Text => true,
+            EncodedWordContext::Comment => match byte {
+                b'(' | b')' | b'"' | b'\\' => false,
+                _ => true
+            },
+            EncodedWordContext::Phrase => false
+        },
+        _ => false
+    }
+}
+
 #[inline]
 fn lower_nibble_to_hex( half_byte: u8 ) -> SoftAsciiChar {
     static CHARS: &[char] = &[
@@ -188,6 +221,9 @@ mod test {
 
     macro_rules! test_ecw_encode {
         ($name:ident, data $data:expr => [$($item:expr),*]) => {
+            test_ecw_encode! { $name, ctx Phrase, data $data => [$($item),*] }
+        };
+        ($name:ident, ctx $ctx:ident, data $data:expr => [$($item:expr),*]) => {
             #[test]
             fn $name() {
                 let test_data = $data;
@@ -196,7 +232,7 @@ mod test {
                     EncodedWordEncoding::QuotedPrintable
                 );
 
-                encoded_word_encode_utf8( test_data, &mut out );
+                encoded_word_encode_utf8( test_data, EncodedWordContext::$ctx, &mut out );
 
                 let expected = &[
                     $($item),*
@@ -237,14 +273,49 @@ mod test {
 
     test_ecw_encode! { bad_chars_in_all_contexts,
         data "?= \t\r\n" => [
-            "=?utf8?Q?=3F=3D=20=09=0D=0A?="
+            "=?utf8?Q?=3F=3D_=09=0D=0A?="
+        ]
+    }
+
+    test_ecw_encode! { bad_chars_in_text_context,
+        ctx Text,
+        data "?= \t\r\n" => [
+            "=?utf8?Q?=3F=3D_=09=0D=0A?="
+        ]
+    }
+
+    test_ecw_encode! { bad_chars_in_comment_context,
+        ctx Comment,
+        data "?= \t\r\n" => [
+            "=?utf8?Q?=3F=3D_=09=0D=0A?="
         ]
     }
 
     test_ecw_encode!{ encode_ascii,
         data  "abcdefghijklmnopqrstuvwxyz \t?=0123456789!@#$%^&*()_+-" => [
-             "=?utf8?Q?abcdefghijklmnopqrstuvwxyz=20=09=3F=3D0123456789!=40=23=24=25=5E?=",
-             "=?utf8?Q?=26*=28=29_+-?="
+             "=?utf8?Q?abcdefghijklmnopqrstuvwxyz_=09=3F=3D0123456789!=40=23=24=25=5E?=",
+             "=?utf8?Q?=26*=28=29=5F+-?="
+        ]
+    }
+
+    test_ecw_encode! { encode_specials_in_phrase_context,
+        ctx Phrase,
+        data "a_b=c?d (e) \"f\", g\\h" => [
+            "=?utf8?Q?a=5Fb=3Dc=3Fd_=28e=29_=22f=22=2C_g=5Ch?="
+        ]
+    }
+
+    test_ecw_encode! { encode_specials_in_comment_context,
+        ctx Comment,
+        data "a_b=c?d (e) \"f\", g\\h" => [
+            "=?utf8?Q?a=5Fb=3Dc=3Fd_=28e=29_=22f=22,_g=5Ch?="
+        ]
+    }
+
+    test_ecw_encode! { encode_specials_in_text_context,
+        ctx Text,
+        data "a_b=c?d (e) \"f\", g\\h" => [
+            "=?utf8?Q?a=5Fb=3Dc=3Fd_(e)_\"f\",_g\\h?="
         ]
     }
 
@@ -267,7 +338,7 @@ mod test {
             //ランダムテキス
             "=?utf8?Q?=E3=83=A9=E3=83=B3=E3=83=80=E3=83=A0=E3=83=86=E3=82=AD=E3=82=B9?=",
             //ト ראַנדאָם
-            "=?utf8?Q?=E3=83=88=20=D7=A8=D7=90=D6=B7=D7=A0=D7=93=D7=90=D6=B8=D7=9D=20?=",
+            "=?utf8?Q?=E3=83=88_=D7=A8=D7=90=D6=B7=D7=A0=D7=93=D7=90=D6=B8=D7=9D_?=",
             //טעקסט
             "=?utf8?Q?=D7=98=D7=A2=D7=A7=D7=A1=D7=98?="
         ]
@@ -287,8 +358,16 @@ mod test {
             ),
             (
                 "=26*=28=29_+-",
+                "&*() +-"
+            ),
+            (
+                "=26*=28=29=5F+-",
                 "&*()_+-"
             ),
+            (
+                "a_b=5Fc_",
+                "a b_c "
+            ),
             (
                 "abcdefghijklmnopqrstuvwxyz=20=09=3F=3D0123456789!=40=23=24=25=5E",
                 "abcdefghijklmnopqrstuvwxyz \t?=0123456789!@#$%^"
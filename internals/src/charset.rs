@@ -0,0 +1,239 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use soft_ascii_string::SoftAsciiStr;
+
+use ::error::EncodingError;
+use ::{Token, TokenStr};
+
+/// A charset as used in mime types, extended mime parameters and encoded words.
+///
+/// Common charsets have their own variant, any other (valid) charset
+/// is represented through `Other`. Labels are case-insensitive, the
+/// canonical label returned by `as_str` is always lowercase.
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Charset {
+    /// `us-ascii`
+    UsAscii,
+    /// `utf-8`
+    Utf8,
+    /// `utf-16`
+    Utf16,
+    /// `iso-8859-1` (latin1)
+    Iso8859_1,
+    /// `iso-8859-2` (latin2)
+    Iso8859_2,
+    /// `iso-8859-15` (latin9)
+    Iso8859_15,
+    /// `windows-1252`
+    Windows1252,
+    /// `shift_jis`
+    ShiftJis,
+    /// `euc-jp`
+    EucJp,
+    /// `iso-2022-jp`
+    Iso2022Jp,
+    /// `gb2312`
+    Gb2312,
+    /// `big5`
+    Big5,
+    /// `koi8-r`
+    Koi8R,
+    /// `unknown-8bit` (RFC 1428)
+    Unknown8Bit,
+    /// any other charset (see `OtherCharset`)
+    Other(OtherCharset)
+}
+
+/// The label of a charset which has no own `Charset` variant.
+///
+/// It can only be created through `Charset::parse`, which guarantees
+/// that it is a lowercase token and not the label (or an alias) of a
+/// charset with its own variant.
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct OtherCharset(Token);
+
+impl OtherCharset {
+
+    /// Returns the (lowercase) label.
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    /// Returns the (lowercase) label as token.
+    pub fn as_token(&self) -> &Token {
+        &self.0
+    }
+}
+
+impl Charset {
+
+    /// Parses a charset label, accepting common aliases (e.g. `utf8`, `latin1`).
+    ///
+    /// # Error
+    ///
+    /// Fails like `TokenStr::new` if the label is not a token.
+    pub fn parse(label: &str) -> Result<Self, EncodingError> {
+        let lower = TokenStr::new(label)?.as_str().to_ascii_lowercase();
+        use self::Charset::*;
+        let charset = match lower.as_str() {
+            "us-ascii" | "ascii" | "ansi_x3.4-1968" | "iso646-us" => UsAscii,
+            "utf-8" | "utf8" => Utf8,
+            "utf-16" | "utf16" => Utf16,
+            "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "latin1" | "l1" => Iso8859_1,
+            "iso-8859-2" | "iso8859-2" | "iso_8859-2" | "latin2" | "l2" => Iso8859_2,
+            "iso-8859-15" | "iso8859-15" | "iso_8859-15" | "latin9" | "latin-9" => Iso8859_15,
+            "windows-1252" | "cp1252" => Windows1252,
+            "shift_jis" | "shift-jis" | "sjis" => ShiftJis,
+            "euc-jp" => EucJp,
+            "iso-2022-jp" => Iso2022Jp,
+            "gb2312" => Gb2312,
+            "big5" => Big5,
+            "koi8-r" => Koi8R,
+            "unknown-8bit" => Unknown8Bit,
+            _ => {
+                let label = Token::new(lower)
+                    .expect("[BUG] lowercasing a token can not make it a non token");
+                Other(OtherCharset(label))
+            }
+        };
+        Ok(charset)
+    }
+
+    /// Returns the canonical (lowercase) label of the charset.
+    pub fn as_str(&self) -> &str {
+        match *self {
+            Charset::Other(ref label) => label.as_str(),
+            ref known => known.static_label()
+                .expect("[BUG] only Other has no static label")
+        }
+    }
+
+    /// Returns the canonical label as `SoftAsciiStr`.
+    pub fn as_soft_str(&self) -> &SoftAsciiStr {
+        //SAFE: all labels are tokens
+        SoftAsciiStr::from_unchecked(self.as_str())
+    }
+
+    /// Returns the canonical label if it's known at compile time, i.e. if self is not `Other`.
+    pub fn static_label(&self) -> Option<&'static str> {
+        use self::Charset::*;
+        let label = match *self {
+            UsAscii => "us-ascii",
+            Utf8 => "utf-8",
+            Utf16 => "utf-16",
+            Iso8859_1 => "iso-8859-1",
+            Iso8859_2 => "iso-8859-2",
+            Iso8859_15 => "iso-8859-15",
+            Windows1252 => "windows-1252",
+            ShiftJis => "shift_jis",
+            EucJp => "euc-jp",
+            Iso2022Jp => "iso-2022-jp",
+            Gb2312 => "gb2312",
+            Big5 => "big5",
+            Koi8R => "koi8-r",
+            Unknown8Bit => "unknown-8bit",
+            Other(_) => return None
+        };
+        Some(label)
+    }
+
+    /// Returns true if us-ascii text is encoded the same way in this charset.
+    ///
+    /// This is false for the stateful `iso-2022-jp` (as it's only compatible
+    /// as long as no escape sequence is used) and for `Other` charsets, as
+    /// nothing is known about them.
+    pub fn is_ascii_compatible(&self) -> bool {
+        use self::Charset::*;
+        match *self {
+            Utf16 | Iso2022Jp | Unknown8Bit | Other(_) => false,
+            _ => true
+        }
+    }
+}
+
+/// Defaults to `UsAscii`, the default charset of RFC 2045.
+impl Default for Charset {
+    fn default() -> Self {
+        Charset::UsAscii
+    }
+}
+
+impl FromStr for Charset {
+    type Err = EncodingError;
+
+    fn from_str(label: &str) -> Result<Self, Self::Err> {
+        Charset::parse(label)
+    }
+}
+
+impl Display for Charset {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        fter.write_str(self.as_str())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::Charset;
+
+    #[test]
+    fn parse_aliases() {
+        let pairs = &[
+            ("utf8", Charset::Utf8),
+            ("UTF-8", Charset::Utf8),
+            ("Utf-8", Charset::Utf8),
+            ("latin1", Charset::Iso8859_1),
+            ("ISO-8859-1", Charset::Iso8859_1),
+            ("ascii", Charset::UsAscii),
+            ("US-ASCII", Charset::UsAscii),
+            ("CP1252", Charset::Windows1252),
+            ("Shift_JIS", Charset::ShiftJis)
+        ];
+        for &(label, ref expected) in pairs.iter() {
+            assert_eq!(&assert_ok!(Charset::parse(label)), expected);
+        }
+    }
+
+    #[test]
+    fn canonical_labels() {
+        assert_eq!(Charset::Utf8.as_str(), "utf-8");
+        assert_eq!(assert_ok!(Charset::parse("LATIN1")).to_string(), "iso-8859-1");
+        assert_eq!(assert_ok!(Charset::parse("X-Custom")).as_str(), "x-custom");
+    }
+
+    #[test]
+    fn other_charsets() {
+        let charset = assert_ok!(Charset::parse("X-Custom"));
+        match charset {
+            Charset::Other(ref other) => assert_eq!(other.as_str(), "x-custom"),
+            ref other => panic!("unexpected charset: {:?}", other)
+        }
+        assert_eq!(charset.static_label(), None);
+        assert!(!charset.is_ascii_compatible());
+    }
+
+    #[test]
+    fn non_token_labels_are_rejected() {
+        assert_err!(Charset::parse(""));
+        assert_err!(Charset::parse("utf 8"));
+        assert_err!(Charset::parse("ütf-8"));
+    }
+
+    #[test]
+    fn non_token_error_has_offset() {
+        let err = assert_err!(Charset::parse("utf 8"));
+        assert_eq!(err.input_offset(), Some(3));
+        let err = assert_err!(Charset::parse(""));
+        assert_eq!(err.input_offset(), Some(0));
+    }
+
+    #[test]
+    fn ascii_compatible() {
+        assert!(Charset::Utf8.is_ascii_compatible());
+        assert!(Charset::Iso8859_1.is_ascii_compatible());
+        assert!(!Charset::Utf16.is_ascii_compatible());
+    }
+}
@@ -1,7 +1,7 @@
-use std::any::{Any, TypeId};
+use std::any::{self, Any, TypeId};
 use std::fmt::{self, Debug};
 use std::result::{ Result as StdResult };
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, OnceLock};
 
 use ::error::EncodingError;
 use super::{EncodingWriter};
@@ -13,14 +13,69 @@ use super::{EncodingWriter};
 /// This trait can be turned into a trait object allowing runtime
 /// genericallity over the "components" if needed.
 pub trait EncodableInHeader: Send + Sync + Any + Debug {
+    /// Encodes the component.
+    ///
+    /// The header name, colon and separator are written by the caller
+    /// (see `EncodingWriter::write_header_name`), so a component must
+    /// never write leading WS, doing so panics in debug builds.
     fn encode(&self, encoder:  &mut EncodingWriter) -> Result<(), EncodingError>;
 
     fn boxed_clone(&self) -> Box<EncodableInHeader>;
 
+    /// Returns the length (in bytes) of the longest part which can't be broken by soft line breaks.
+    ///
+    /// This allows checking if a header can be encoded without breaching
+    /// the hard line length limit _before_ encoding it, see
+    /// `encoder::check_unbreakable_run`. The default returns `None` which
+    /// means it is unknown, in which case the limit is only checked while
+    /// encoding.
+    fn max_unbreakable_run(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns the bounds on the number of bytes the encoded component will have.
+    ///
+    /// Like `Iterator::size_hint` this returns a lower bound and an optional
+    /// upper bound. It's used to pre-size the buffer (see `EncodingBuffer::reserve`)
+    /// and must not be relied on for correctness, through a lower bound which is
+    /// bigger than the actual size is a bug in the implementation. The bounds
+    /// do not include any soft line breaks inserted by the encoder.
+    ///
+    /// The default returns `(0, None)`.
+    fn encoded_size_hint(&self) -> (usize, Option<usize>) {
+        (0, None)
+    }
+
+    /// Materializes the component if it's lazy and returns the materialized component.
+    ///
+    /// This is only relevant for lazily computed components (see `LazyComponent`),
+    /// all other components return `Ok(None)`. Validators which need to inspect
+    /// the value of a component should use `materialize_and_downcast_ref` which
+    /// is based on this method.
+    fn materialize(&self) -> Result<Option<&(EncodableInHeader + 'static)>, EncodingError> {
+        Ok(None)
+    }
+
     #[doc(hidden)]
     fn type_id( &self ) -> TypeId {
         TypeId::of::<Self>()
     }
+
+    #[doc(hidden)]
+    fn type_name( &self ) -> &'static str {
+        any::type_name::<Self>()
+    }
+}
+
+/// Error returned by `downcast_checked` if the component has a different type.
+///
+/// The type names are meant for diagnostics only, they are not stable.
+#[derive(Debug, Fail, Clone, Copy, PartialEq, Eq, Hash)]
+#[fail(display = "expected component of type {} got {}", expected, actual)]
+#[non_exhaustive]
+pub struct DowncastError {
+    pub expected: &'static str,
+    pub actual: &'static str
 }
 
 //TODO we now could use MOPA or similar crates
@@ -32,6 +87,19 @@ impl EncodableInHeader {
     }
 
 
+    /// Like `downcast_ref` but returns an error naming both types on mismatch.
+    ///
+    /// Prefer this over `downcast_ref` where a mismatch is a bug, so that
+    /// it doesn't get silently ignored. Callers to which a mismatch is
+    /// not an error can explicitly skip it using `.ok()`.
+    pub fn downcast_checked<T: EncodableInHeader>(&self) -> Result<&T, DowncastError> {
+        self.downcast_ref::<T>()
+            .ok_or_else(|| DowncastError {
+                expected: any::type_name::<T>(),
+                actual: self.type_name()
+            })
+    }
+
     #[inline]
     pub fn downcast_ref<T: EncodableInHeader>(&self) -> Option<&T> {
         if self.is::<T>() {
@@ -41,6 +109,21 @@ impl EncodableInHeader {
         }
     }
 
+    /// Like `downcast_ref` but sees through lazy components (materializing them if needed).
+    ///
+    /// # Error
+    ///
+    /// Fails if materializing the component fails.
+    pub fn materialize_and_downcast_ref<T: EncodableInHeader>(&self)
+        -> Result<Option<&T>, EncodingError>
+    {
+        if let Some(component) = self.downcast_ref::<T>() {
+            return Ok(Some(component));
+        }
+        let inner = self.materialize()?;
+        Ok(inner.and_then(|inner| inner.downcast_ref::<T>()))
+    }
+
     #[inline]
     pub fn downcast_mut<T: EncodableInHeader>(&mut self) -> Option<&mut T> {
         if self.is::<T>() {
@@ -59,7 +142,18 @@ impl Clone for Box<EncodableInHeader> {
 }
 
 
-pub trait EncodableInHeaderBoxExt: Sized {
+mod sealed {
+    use super::EncodableInHeader;
+
+    pub trait Sealed {}
+    impl Sealed for Box<EncodableInHeader> {}
+    impl Sealed for Box<EncodableInHeader+Send> {}
+}
+
+/// Extension trait for downcasting boxed components.
+///
+/// This trait is sealed, i.e. it can not be implemented outside of this crate.
+pub trait EncodableInHeaderBoxExt: Sized + sealed::Sealed {
     fn downcast<T: EncodableInHeader>(self) -> StdResult<Box<T>, Self>;
 }
 
@@ -184,4 +278,113 @@ impl<FN: 'static> Debug for EncodeClosure<FN>
     fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
         write!(fter, "EncodeClosure(..)")
     }
-}
\ No newline at end of file
+}
+
+/// A component which is only computed when it's needed (e.g. when encoding it).
+///
+/// The closure is called at most once successfully, the result is cached.
+/// If the closure fails the error is returned and it will be called
+/// again the next time the component is needed.
+///
+/// Clones share the closure and, if already computed, clone the computed
+/// component, but clones computing the component do not affect each other.
+pub struct LazyComponent<C, FN>
+    where C: EncodableInHeader + Clone,
+          FN: Send + Sync + 'static + Fn() -> Result<C, EncodingError>
+{
+    init: Arc<FN>,
+    init_lock: Mutex<()>,
+    value: OnceLock<C>
+}
+
+impl<C, FN> LazyComponent<C, FN>
+    where C: EncodableInHeader + Clone,
+          FN: Send + Sync + 'static + Fn() -> Result<C, EncodingError>
+{
+    /// Creates a new lazy component which will be computed by calling `init`.
+    pub fn new(init: FN) -> Self {
+        LazyComponent {
+            init: Arc::new(init),
+            init_lock: Mutex::new(()),
+            value: OnceLock::new()
+        }
+    }
+
+    /// Returns the computed component, computing it if this wasn't done yet.
+    pub fn force(&self) -> Result<&C, EncodingError> {
+        if let Some(value) = self.value.get() {
+            return Ok(value);
+        }
+        // makes sure concurrent calls won't call the closure twice
+        let _guard = self.init_lock.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(value) = self.value.get() {
+            return Ok(value);
+        }
+        let value = (self.init)()?;
+        Ok(self.value.get_or_init(|| value))
+    }
+
+    /// Returns the component if it was already computed.
+    pub fn get(&self) -> Option<&C> {
+        self.value.get()
+    }
+
+    /// Returns true if the component was already computed.
+    pub fn is_materialized(&self) -> bool {
+        self.value.get().is_some()
+    }
+}
+
+impl<C, FN> EncodableInHeader for LazyComponent<C, FN>
+    where C: EncodableInHeader + Clone,
+          FN: Send + Sync + 'static + Fn() -> Result<C, EncodingError>
+{
+    fn encode(&self, encoder:  &mut EncodingWriter) -> Result<(), EncodingError> {
+        self.force()?.encode(encoder)
+    }
+
+    fn boxed_clone(&self) -> Box<EncodableInHeader> {
+        Box::new(self.clone())
+    }
+
+    fn max_unbreakable_run(&self) -> Option<usize> {
+        self.get().and_then(|value| value.max_unbreakable_run())
+    }
+
+    fn encoded_size_hint(&self) -> (usize, Option<usize>) {
+        self.get()
+            .map(|value| value.encoded_size_hint())
+            .unwrap_or((0, None))
+    }
+
+    fn materialize(&self) -> Result<Option<&(EncodableInHeader + 'static)>, EncodingError> {
+        let value: &(EncodableInHeader + 'static) = self.force()?;
+        Ok(Some(value))
+    }
+}
+
+impl<C, FN> Clone for LazyComponent<C, FN>
+    where C: EncodableInHeader + Clone,
+          FN: Send + Sync + 'static + Fn() -> Result<C, EncodingError>
+{
+    fn clone(&self) -> Self {
+        LazyComponent {
+            init: self.init.clone(),
+            init_lock: Mutex::new(()),
+            value: self.value.clone()
+        }
+    }
+}
+
+impl<C, FN> Debug for LazyComponent<C, FN>
+    where C: EncodableInHeader + Clone,
+          FN: Send + Sync + 'static + Fn() -> Result<C, EncodingError>
+{
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        match self.get() {
+            Some(value) => write!(fter, "LazyComponent({:?})", value),
+            None => write!(fter, "LazyComponent(..)")
+        }
+    }
+}
@@ -15,16 +15,13 @@
 //! writing tests easier. (Through it should _only_ be enabled
 //! for testing and maybe debugging in some cases).
 use std::borrow::Cow;
-use std::str;
+use std::{str, cmp};
 
 use failure::Fail;
 use soft_ascii_string::{SoftAsciiStr, SoftAsciiChar};
 
-use grammar::is_atext;
-use ::utils::{
-    is_utf8_continuation_byte,
-    vec_insert_bytes
-};
+use grammar::{is_atext, is_ftext};
+use ::utils::vec_insert_bytes;
 use ::MailType;
 use ::error::{
     EncodingError, EncodingErrorKind,
@@ -36,21 +33,140 @@ use ::error::{
 mod trace;
 #[cfg_attr(test, macro_use)]
 mod encodable;
+mod pool;
 
 
 #[cfg(feature="traceing")]
 pub use self::trace::*;
 pub use self::encodable::*;
+pub use self::pool::*;
 
 /// as specified in RFC 5322 not including CRLF
+///
+/// Like all line lengths in this crate it's measured in bytes (not
+/// chars or display width), i.e. non us-ascii chars count as the
+/// length of their UTF-8 representation.
 pub const LINE_LEN_SOFT_LIMIT: usize = 78;
 /// as specified in RFC 5322 (mail) + RFC 5321 (smtp) not including CRLF
+///
+/// Measured in bytes, see `LINE_LEN_SOFT_LIMIT`.
 pub const LINE_LEN_HARD_LIMIT: usize = 998;
 
 
+/// Checks if the longest unbreakable part of a component can be written at all.
+///
+/// `prefix_len` is the number of bytes on the line before the component
+/// (e.g. the length of `"Subject: "`) as the unbreakable part might be
+/// the first part of the component in which case it can't be moved to a
+/// new line.
+///
+/// # Error
+///
+/// Fails with `HardLineLengthLimitBreached` if the component returns a
+/// `max_unbreakable_run` which can not fit into a line.
+pub fn check_unbreakable_run(component: &EncodableInHeader, prefix_len: usize)
+    -> Result<(), EncodingError>
+{
+    if let Some(run_len) = component.max_unbreakable_run() {
+        if prefix_len.saturating_add(run_len) > LINE_LEN_HARD_LIMIT {
+            let mut err = EncodingError::from(EncodingErrorKind::HardLineLengthLimitBreached);
+            err.set_str_context(format!(
+                "unbreakable part of {} bytes after a prefix of {} bytes", run_len, prefix_len));
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// The line ending written by the encoder.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum LineEnding {
+    /// `"\r\n"` as required by RFC 5322 and RFC 5321 (smtp), the default
+    CrLf,
+    /// `"\n"`, e.g. for writing maildir/mbox files or test fixtures
+    ///
+    /// **Warning: Output using `Lf` is _not_ a valid mail, it can not be
+    /// send (e.g. through smtp) without converting the line endings back
+    /// to `CrLf`.**
+    Lf
+}
+
+impl LineEnding {
+
+    /// Returns the line ending as str.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Lf => "\n"
+        }
+    }
+}
+
+impl Default for LineEnding {
+    fn default() -> Self {
+        LineEnding::CrLf
+    }
+}
+
+/// Specifies if the encoder folds lines (i.e. inserts soft line breaks).
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum FoldingMode {
+    /// Fold lines to keep the soft line length limit if possible, the default.
+    Fold,
+    /// Never insert soft line breaks.
+    ///
+    /// This also disables the hard line length limit (there is no
+    /// way to keep it without folding). It's meant for producing
+    /// unfolded header values, e.g. for signing them, output produced
+    /// this way should not be send as a mail.
+    NeverFold
+}
+
+impl Default for FoldingMode {
+    fn default() -> Self {
+        FoldingMode::Fold
+    }
+}
+
+/// The whitespace written between the colon after a header name and the value.
+///
+/// See `EncodingWriter::write_header_name`.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum NameValueSeparator {
+    /// A single space (`"Name: value"`), the default.
+    Space,
+    /// A single horizontal tab (`"Name:\tvalue"`).
+    ///
+    /// Valid but unusual, it's only meant for consumers which need it.
+    Tab
+}
+
+impl NameValueSeparator {
+
+    /// Returns the separator as ascii str.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            NameValueSeparator::Space => " ",
+            NameValueSeparator::Tab => "\t"
+        }
+    }
+}
+
+impl Default for NameValueSeparator {
+    fn default() -> Self {
+        NameValueSeparator::Space
+    }
+}
+
 /// EncodingBuffer for a Mail providing a buffer for encodable traits.
 pub struct EncodingBuffer {
     mail_type: MailType,
+    line_ending: LineEnding,
+    folding_mode: FoldingMode,
+    name_value_separator: NameValueSeparator,
     buffer: Vec<u8>,
     #[cfg(feature="traceing")]
     pub trace: Vec<TraceToken>
@@ -60,8 +176,22 @@ impl EncodingBuffer {
 
     /// Create a new buffer only allowing input compatible with a the specified mail type.
     pub fn new(mail_type: MailType) -> Self {
+        EncodingBuffer::with_line_ending(mail_type, LineEnding::default())
+    }
+
+    /// Create a new buffer which uses the given line ending.
+    ///
+    /// The line ending is used for _all_ line breaks written by
+    /// the buffer and its writers, including soft line breaks and
+    /// newlines written by components (which still have to write
+    /// `"\r\n"`). Note that `LineEnding::Lf` will not produce valid
+    /// mails (see `LineEnding`).
+    pub fn with_line_ending(mail_type: MailType, line_ending: LineEnding) -> Self {
         EncodingBuffer {
             mail_type,
+            line_ending,
+            folding_mode: FoldingMode::default(),
+            name_value_separator: NameValueSeparator::default(),
             buffer: Vec::new(),
             #[cfg(feature="traceing")]
             trace: Vec::new()
@@ -73,17 +203,70 @@ impl EncodingBuffer {
         self.mail_type
     }
 
+    /// Returns the line ending used by the buffer.
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    /// Returns the folding mode used by writers of this buffer.
+    pub fn folding_mode(&self) -> FoldingMode {
+        self.folding_mode
+    }
+
+    /// Sets the folding mode used by writers created after this call.
+    pub fn set_folding_mode(&mut self, folding_mode: FoldingMode) {
+        self.folding_mode = folding_mode;
+    }
+
+    /// Returns the separator written by `EncodingWriter::write_header_name`.
+    pub fn name_value_separator(&self) -> NameValueSeparator {
+        self.name_value_separator
+    }
+
+    /// Sets the separator used by writers created after this call.
+    pub fn set_name_value_separator(&mut self, separator: NameValueSeparator) {
+        self.name_value_separator = separator;
+    }
+
+    /// Resets the buffer so that it can be reused for another mail.
+    ///
+    /// All written data (and the trace in test builds) is discarded
+    /// but the allocated capacity is kept. The line ending is kept,
+    /// too, but the mail type is replaced with the given one and the
+    /// folding mode as well as the name value separator are reset to
+    /// their defaults.
+    pub fn reset(&mut self, mail_type: MailType) {
+        self.mail_type = mail_type;
+        self.folding_mode = FoldingMode::default();
+        self.name_value_separator = NameValueSeparator::default();
+        self.buffer.clear();
+        #[cfg(feature="traceing")]
+        { self.trace.clear(); }
+    }
+
+    /// Reserves capacity for at least `additional` more bytes.
+    ///
+    /// This can be used with `EncodableInHeader::encoded_size_hint` to
+    /// avoid reallocating the buffer multiple times when writing headers.
+    pub fn reserve(&mut self, additional: usize) {
+        self.buffer.reserve(additional)
+    }
+
     /// returns a new EncodingWriter which contains
     /// a mutable reference to the current string buffer
     ///
     pub fn writer(&mut self) -> EncodingWriter {
         #[cfg(not(feature="traceing"))]
         {
-            EncodingWriter::new(self.mail_type, &mut self.buffer)
+            EncodingWriter::new(
+                self.mail_type, self.line_ending, self.folding_mode,
+                self.name_value_separator, &mut self.buffer)
         }
         #[cfg(feature="traceing")]
         {
-            EncodingWriter::new(self.mail_type, &mut self.buffer, &mut self.trace)
+            EncodingWriter::new(
+                self.mail_type, self.line_ending, self.folding_mode,
+                self.name_value_separator, &mut self.buffer, &mut self.trace)
         }
     }
 
@@ -113,17 +296,22 @@ impl EncodingBuffer {
 
     pub fn write_blank_line(&mut self) {
         //TODO/BENCH push_str vs. extends(&[u8])
-        self.buffer.extend("\r\n".as_bytes());
+        self.buffer.extend(self.line_ending.as_str().as_bytes());
         #[cfg(feature="traceing")]
         { self.trace.push(TraceToken::BlankLine); }
     }
 
     /// writes a body to the internal buffer, without verifying it's correctness
+    ///
+    /// If the body does not end with the line ending used by the
+    /// buffer it is appended. Line breaks _inside_ the body are not
+    /// converted.
     pub fn write_body_unchecked(&mut self, body: &impl AsRef<[u8]>) {
         let slice = body.as_ref();
+        let line_ending = self.line_ending.as_str().as_bytes();
         self.buffer.extend(slice);
-        if !slice.ends_with(b"\r\n") {
-            self.buffer.extend(b"\r\n");
+        if !slice.ends_with(line_ending) {
+            self.buffer.extend(line_ending);
         }
     }
 
@@ -184,7 +372,7 @@ impl Into<(MailType, Vec<u8>)> for EncodingBuffer {
 #[cfg(feature="traceing")]
 impl Into<(MailType, Vec<u8>, Vec<TraceToken>)> for EncodingBuffer {
     fn into(self) -> (MailType, Vec<u8>, Vec<TraceToken>) {
-        let EncodingBuffer { mail_type, buffer, trace } = self;
+        let EncodingBuffer { mail_type, buffer, trace, .. } = self;
         (mail_type, buffer, trace)
     }
 }
@@ -211,6 +399,9 @@ pub struct EncodingWriter<'a> {
     #[cfg(feature="traceing")]
     trace: &'a mut Vec<TraceToken>,
     mail_type: MailType,
+    line_ending: LineEnding,
+    folding_mode: FoldingMode,
+    name_value_separator: NameValueSeparator,
     line_start_idx: usize,
     last_fws_idx: usize,
     skipped_cr: bool,
@@ -222,6 +413,8 @@ pub struct EncodingWriter<'a> {
     /// on the current line (false if there was no FWS yet on the current
     /// line).
     content_before_fws: bool,
+    /// where we are wrt. the start of the header (see `HeaderStart`)
+    header_start: HeaderStart,
     header_start_idx: usize,
     #[cfg(feature="traceing")]
     trace_start_idx: usize
@@ -245,17 +438,24 @@ impl<'inner> EncodingWriter<'inner> {
     #[cfg(not(feature="traceing"))]
     fn new(
         mail_type: MailType,
+        line_ending: LineEnding,
+        folding_mode: FoldingMode,
+        name_value_separator: NameValueSeparator,
         buffer: &'inner mut Vec<u8>,
     ) -> Self {
         let start_idx = buffer.len();
         EncodingWriter {
             buffer,
             mail_type,
+            line_ending,
+            folding_mode,
+            name_value_separator,
             line_start_idx: start_idx,
             last_fws_idx: start_idx,
             skipped_cr: false,
             content_since_fws: false,
             content_before_fws: false,
+            header_start: HeaderStart::Value,
             header_start_idx: start_idx
         }
     }
@@ -263,6 +463,9 @@ impl<'inner> EncodingWriter<'inner> {
     #[cfg(feature="traceing")]
     fn new(
         mail_type: MailType,
+        line_ending: LineEnding,
+        folding_mode: FoldingMode,
+        name_value_separator: NameValueSeparator,
         buffer: &'inner mut Vec<u8>,
         trace: &'inner mut Vec<TraceToken>
     ) -> Self {
@@ -272,11 +475,15 @@ impl<'inner> EncodingWriter<'inner> {
             buffer,
             trace,
             mail_type,
+            line_ending,
+            folding_mode,
+            name_value_separator,
             line_start_idx: start_idx,
             last_fws_idx: start_idx,
             skipped_cr: false,
             content_since_fws: false,
             content_before_fws: false,
+            header_start: HeaderStart::Value,
             header_start_idx: start_idx,
             trace_start_idx
         }
@@ -289,6 +496,7 @@ impl<'inner> EncodingWriter<'inner> {
         self.skipped_cr = false;
         self.content_since_fws = false;
         self.content_before_fws = false;
+        self.header_start = HeaderStart::Value;
         self.header_start_idx = start_idx;
         #[cfg(feature="traceing")]
         { self.trace_start_idx = self.trace.len(); }
@@ -306,6 +514,24 @@ impl<'inner> EncodingWriter<'inner> {
         self.mail_type
     }
 
+    /// Returns the line ending used for new lines.
+    #[inline]
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    /// Returns the folding mode of this writer.
+    #[inline]
+    pub fn folding_mode(&self) -> FoldingMode {
+        self.folding_mode
+    }
+
+    /// Returns the separator written by `write_header_name`.
+    #[inline]
+    pub fn name_value_separator(&self) -> NameValueSeparator {
+        self.name_value_separator
+    }
+
     /// Returns true if the current line has content, i.e. any non WS char.
     #[inline]
     pub fn line_has_content(&self) -> bool {
@@ -313,6 +539,9 @@ impl<'inner> EncodingWriter<'inner> {
     }
 
     /// Returns the length of the current line in bytes.
+    ///
+    /// This does not include the line ending, independent of
+    /// which line ending is used.
     #[inline]
     pub fn current_line_byte_length(&self) -> usize {
         self.buffer.len() - self.line_start_idx
@@ -321,11 +550,19 @@ impl<'inner> EncodingWriter<'inner> {
     /// marks the current position a a place where a soft
     /// line break (i.e. "\r\n ") can be inserted
     ///
+    /// The position is ignored if it is directly after a header name written
+    /// with `write_header_name`, as folding there would produce a first line
+    /// without any value (which some parsers do not handle well). If needed
+    /// the soft line length limit is exceeded instead.
+    ///
     /// # Trace (test build only)
     /// does push a `MarkFWS` Token
     pub fn mark_fws_pos(&mut self) {
         #[cfg(feature="traceing")]
         { self.trace.push(TraceToken::MarkFWS) }
+        if self.header_start == HeaderStart::AfterSeparator {
+            return;
+        }
         self.content_before_fws |= self.content_since_fws;
         self.content_since_fws = false;
         self.last_fws_idx = self.buffer.len()
@@ -370,6 +607,85 @@ impl<'inner> EncodingWriter<'inner> {
         self.internal_write_str(s.as_str())
     }
 
+    /// writes the decimal representation of the number
+    ///
+    /// No allocation is done, the number is formatted into a
+    /// buffer on the stack and then written like with `write_str`.
+    ///
+    /// # Error
+    /// like `write_str`, i.e. only if the hard line length limit is breached
+    ///
+    /// # Trace (test build only)
+    /// does push `NowStr` and then can push `Text`
+    pub fn write_u64(&mut self, number: u64) -> Result<(), EncodingError> {
+        let mut buf = [0u8; MAX_DECIMAL_LEN];
+        let start_idx = format_decimal(number, false, &mut buf);
+        self.write_decimal(&buf[start_idx..])
+    }
+
+    /// writes the decimal representation of the number (see `write_u64`)
+    pub fn write_usize(&mut self, number: usize) -> Result<(), EncodingError> {
+        self.write_u64(number as u64)
+    }
+
+    /// writes the decimal representation of the number, including a leading `'-'` if negative
+    ///
+    /// See `write_u64`.
+    pub fn write_i64(&mut self, number: i64) -> Result<(), EncodingError> {
+        let mut buf = [0u8; MAX_DECIMAL_LEN];
+        // works for i64::MIN, too, as the abs value is computed in u64
+        let abs = if number < 0 { (number as u64).wrapping_neg() } else { number as u64 };
+        let start_idx = format_decimal(abs, number < 0, &mut buf);
+        self.write_decimal(&buf[start_idx..])
+    }
+
+    fn write_decimal(&mut self, decimal: &[u8]) -> Result<(), EncodingError> {
+        let decimal = str::from_utf8(decimal)
+            .expect("[BUG] decimal numbers are us-ascii");
+        //SAFE: digits and '-' are us-ascii
+        self.write_str(SoftAsciiStr::from_unchecked(decimal))
+    }
+
+    /// writes the header name followed by `':'` and the name value separator
+    ///
+    /// This is the way header names should be written, it writes e.g.
+    /// `"Subject: "` (or `"Subject:\t"` with `NameValueSeparator::Tab`).
+    /// The component writing the value afterwards must never start with
+    /// WS (including `write_fws`), in debug builds doing so panics.
+    ///
+    /// If the value is empty (i.e. nothing is written before `finish_header`)
+    /// the separator is removed, resulting in e.g. `"Comments:\r\n"`.
+    ///
+    /// # Error
+    ///
+    /// Fails with `Malformed` if `name` is empty or contains chars not
+    /// allowed in header names (i.e. anything but printable us-ascii
+    /// except `':'`), in which case nothing is written. The input offset
+    /// of the error points to the first invalid char.
+    ///
+    /// # Trace (test build only)
+    /// does push `NowStr` and then can push `Text`
+    pub fn write_header_name(&mut self, name: &SoftAsciiStr) -> Result<(), EncodingError> {
+        debug_assert!(!self.has_unfinished_parts(), "header name written after header start");
+        let invalid_idx = name.as_str().char_indices()
+            .find(|&(_, ch)| !is_ftext(ch))
+            .map(|(idx, _)| idx);
+        if name.as_str().is_empty() || invalid_idx.is_some() {
+            let mut err = EncodingError::from((EncodingErrorKind::Malformed, self.mail_type()));
+            err.set_str_context(name.as_str());
+            err.set_input_offset(invalid_idx.unwrap_or(0));
+            return Err(err);
+        }
+        let separator = self.name_value_separator.as_str();
+        self.transaction(|writer| {
+            writer.write_str(name)?;
+            writer.write_str(SoftAsciiStr::from_unchecked(":"))?;
+            writer.write_str(SoftAsciiStr::from_unchecked(separator))
+        })?;
+        self.header_start = HeaderStart::AfterSeparator;
+        Ok(())
+    }
+
 
     /// writes a utf8 str into a buffer for an internationalized mail
     ///
@@ -379,10 +695,9 @@ impl<'inner> EncodingWriter<'inner> {
     /// - fails with `GeneralFailure` if the hard line length limit is reached
     /// - or if the buffer would contain a orphan '\r' or '\n' after the write
     ///
-    /// Note that in case of an error part of the content might already
-    /// have been written to the buffer, therefore it is recommended
-    /// to call `undo_header` after an error (especially if the
-    /// handle is droped after this!)
+    /// The write is transactional, i.e. if it fails with `GeneralFailure`
+    /// all parts written by this call are rolled back (see
+    /// `ConditionalWriteResult` for details).
     ///
     /// # Trace (test build only)
     /// does push `NowUtf8` and then can push `Text`,`CRLF`
@@ -390,9 +705,11 @@ impl<'inner> EncodingWriter<'inner> {
         -> ConditionalWriteResult<'short, 'inner>
     {
         if self.mail_type().is_internationalized() {
-            #[cfg(feature="traceing")]
-            { self.trace.push(TraceToken::NowUtf8) }
-            self.internal_write_str(s).into()
+            self.transaction(|this| {
+                #[cfg(feature="traceing")]
+                { this.trace.push(TraceToken::NowUtf8) }
+                this.internal_write_str(s)
+            }).into()
         } else {
             ConditionalWriteResult::ConditionFailure(self)
         }
@@ -438,10 +755,9 @@ impl<'inner> EncodingWriter<'inner> {
     ///   (excluding a tailing `'\r'` as it is still valid if followed by an
     ///    `'\n'`)
     ///
-    /// Note that in case of an error part of the content might already
-    /// have been written to the buffer, therefore it is recommended
-    /// to call `undo_header` after an error (especially if the
-    /// handle is doped after this!)
+    /// The write is transactional, i.e. if it fails with `GeneralFailure`
+    /// all parts written by this call are rolled back (see
+    /// `ConditionalWriteResult` for details).
     ///
     /// # Trace (test build only)
     /// does push `NowAText` and then can push `Text`
@@ -450,10 +766,12 @@ impl<'inner> EncodingWriter<'inner> {
         -> ConditionalWriteResult<'short, 'inner>
     {
         if s.chars().all( |ch| is_atext( ch, self.mail_type() ) ) {
-            #[cfg(feature="traceing")]
-            { self.trace.push(TraceToken::NowAText) }
-            // the ascii or not aspect is already converted by `is_atext`
-            self.internal_write_str(s).into()
+            self.transaction(|this| {
+                #[cfg(feature="traceing")]
+                { this.trace.push(TraceToken::NowAText) }
+                // the ascii or not aspect is already converted by `is_atext`
+                this.internal_write_str(s)
+            }).into()
         } else {
             ConditionalWriteResult::ConditionFailure(self)
         }
@@ -463,23 +781,32 @@ impl<'inner> EncodingWriter<'inner> {
     /// then writes it _without additional checks_ to the buffer if `cond` returned
     /// true
     ///
+    /// Like `write_if_utf8` the write is transactional.
+    ///
+    /// # Trace (test build only)
+    /// does push `NowCondText` and then can push `Text`,`CRLF`
     pub fn write_if<'short, FN>(&'short mut self, s: &str, cond: FN)
         -> ConditionalWriteResult<'short, 'inner>
         where FN: FnOnce(&str) -> bool
     {
         if cond(s) {
-            #[cfg(feature="traceing")]
-            { self.trace.push(TraceToken::NowCondText) }
-            // the ascii or not aspect is already converted by `is_atext`
-            self.internal_write_str(s).into()
+            self.transaction(|this| {
+                #[cfg(feature="traceing")]
+                { this.trace.push(TraceToken::NowCondText) }
+                this.internal_write_str(s)
+            }).into()
         } else {
             ConditionalWriteResult::ConditionFailure(self)
         }
     }
 
     /// writes a string to the encoder without checking if it is compatible
-    /// with the mail type, if not used correctly this can write Utf8 to
-    /// an Ascii Mail, which is incorrect but has to be safe wrt. rust's safety.
+    /// with the mail type
+    ///
+    /// If not used correctly this would write Utf8 to an Ascii Mail, which
+    /// is incorrect. This is still caught when writing the first non us-ascii
+    /// byte, an `InvalidTextEncoding` error naming the header and offset is
+    /// returned in that case.
     ///
     /// Use it as a replacement for cases similar to following:
     ///
@@ -523,7 +850,8 @@ impl<'inner> EncodingWriter<'inner> {
 
     /// finishes the writing of a header
     ///
-    /// It makes sure the header ends in "\r\n".
+    /// It makes sure the header ends in "\r\n" (or
+    /// whichever line ending is used).
     /// If the header ends in a orphan '\r' this
     /// method will just "use" it for the "\r\n".
     ///
@@ -533,12 +861,19 @@ impl<'inner> EncodingWriter<'inner> {
     /// the WS padding. This is needed as "blank" lines
     /// are not allowed.
     ///
+    /// If the header name was written with `write_header_name` and
+    /// nothing was written after it the separator is removed, so that
+    /// e.g. `"Comments: "` is written as `"Comments:\r\n"`.
+    ///
     /// # Trace (test build only)
     /// - can push 0-1 of `[CRLF, TruncateToCRLF]`
     /// - then does push `End`
     /// - calling `finish_current()` multiple times in a row
     ///   will not generate multiple `End` tokens, just one
     pub fn finish_header(&mut self) {
+        if self.header_start == HeaderStart::AfterSeparator {
+            self.strip_ws_after_colon();
+        }
         self.start_new_line();
         #[cfg(feature="traceing")]
         { if let Some(&TraceToken::End) = self.trace.last() {}
@@ -589,34 +924,223 @@ impl<'inner> EncodingWriter<'inner> {
     //---------------------------------------------------------------------------------------------/
     //-///////////////////////////          private methods               ////////////////////////-/
 
+    /// runs `func` and rolls back all writes done by it if it fails
+    fn transaction<FN>(&mut self, func: FN) -> Result<(), EncodingError>
+        where FN: FnOnce(&mut Self) -> Result<(), EncodingError>
+    {
+        let mark = self.mark();
+        let res = func(self);
+        if res.is_err() {
+            self.rollback(mark);
+        }
+        res
+    }
+
+    /// creates a snapshot of the current state which can be used to roll back to it
+    fn mark(&self) -> WriterMark {
+        let buffer_len = self.buffer.len();
+        // a soft line break can still be inserted at the last fws, which would
+        // move all bytes after it, so we need to know what was there
+        let breakable_fws_byte =
+            if self.content_before_fws
+                && self.last_fws_idx > self.line_start_idx
+                && self.last_fws_idx < buffer_len
+            {
+                Some(self.buffer[self.last_fws_idx])
+            } else {
+                None
+            };
+        // starting a new line truncates a whitespace only line
+        let blank_line_tail =
+            if !self.line_has_content() && self.line_start_idx < buffer_len {
+                Some(self.buffer[self.line_start_idx..].to_owned())
+            } else {
+                None
+            };
+
+        WriterMark {
+            buffer_len,
+            line_start_idx: self.line_start_idx,
+            last_fws_idx: self.last_fws_idx,
+            breakable_fws_byte,
+            blank_line_tail,
+            skipped_cr: self.skipped_cr,
+            content_since_fws: self.content_since_fws,
+            content_before_fws: self.content_before_fws,
+            header_start: self.header_start,
+            #[cfg(feature="traceing")]
+            trace_len: self.trace.len()
+        }
+    }
+
+    /// rolls back all writes done since `mark` was created
+    ///
+    /// `mark` has to be created by this writer and no `finish_header`,
+    /// `undo_header` or `commit_partial_header` call must have happened
+    /// since then.
+    fn rollback(&mut self, mark: WriterMark) {
+        if let Some(ref tail) = mark.blank_line_tail {
+            self.buffer.truncate(mark.line_start_idx);
+            self.buffer.extend(tail);
+        } else {
+            let fws_idx = mark.last_fws_idx;
+            let mut inserted = 0;
+            if let Some(fws_byte) = mark.breakable_fws_byte {
+                // the buffer never contains a `'\r'` or `'\n'` which is not part
+                // of a line ending, so if the byte changed a line break was inserted
+                if self.buffer.get(fws_idx) != Some(&fws_byte) {
+                    inserted = self.line_ending.as_str().len() + match fws_byte {
+                        b' ' | b'\t' => 0,
+                        _ => 1
+                    };
+                }
+            }
+            self.buffer.truncate(mark.buffer_len + inserted);
+            if inserted > 0 {
+                self.buffer.drain(fws_idx..fws_idx+inserted);
+            }
+        }
+
+        self.line_start_idx = mark.line_start_idx;
+        self.last_fws_idx = mark.last_fws_idx;
+        self.skipped_cr = mark.skipped_cr;
+        self.content_since_fws = mark.content_since_fws;
+        self.content_before_fws = mark.content_before_fws;
+        self.header_start = mark.header_start;
+        #[cfg(feature="traceing")]
+        { self.trace.truncate(mark.trace_len); }
+    }
+
     /// this might partial write some data and then fail.
     /// while we could implement a undo option it makes
     /// little sense for the use case the generally available
     /// `undo_header` is enough.
     fn internal_write_str(&mut self, s: &str)  -> Result<(), EncodingError>  {
-        if s.is_empty() {
-            return Ok(());
+        let bytes = s.as_bytes();
+        let mut idx = 0;
+        while idx < bytes.len() {
+            // runs of ascii chars which are neither '\r' nor '\n' can be
+            // copied in bulk as long as they fit into the soft line length
+            // limit, as no line breaking needs to be done for them
+            let clean_len = bytes[idx..].iter()
+                .position(|&bch| needs_char_wise_handling(bch))
+                .unwrap_or(bytes.len() - idx);
+            let budget = match self.folding_mode {
+                FoldingMode::Fold => LINE_LEN_SOFT_LIMIT
+                    .saturating_sub(self.current_line_byte_length()),
+                FoldingMode::NeverFold => clean_len
+            };
+            let bulk_len = cmp::min(clean_len, budget);
+
+            if bulk_len > 0 && !self.skipped_cr {
+                let end = idx + bulk_len;
+                self.internal_write_clean_run(&s[idx..end]);
+                idx = end;
+            } else {
+                //UNWRAP_SAFE: idx < bytes.len() and idx is always on a char boundary
+                let ch_len = s[idx..].chars().next().unwrap().len_utf8();
+                let end = idx + ch_len;
+                self.internal_write_char(&s[idx..end])?;
+                idx = end;
+            }
+        }
+        Ok(())
+    }
+
+    /// Bulk writes a run of ascii chars not containing `'\r'` or `'\n'`.
+    ///
+    /// The caller has to make sure that writing the run will not exceed
+    /// the soft line length limit (if folding) and that no `'\r'` was
+    /// skipped before.
+    fn internal_write_clean_run(&mut self, run: &str) {
+        debug_assert!(!run.bytes().any(needs_char_wise_handling));
+        debug_assert!(!self.skipped_cr);
+        debug_assert!(self.folding_mode == FoldingMode::NeverFold
+            || self.current_line_byte_length() + run.len() <= LINE_LEN_SOFT_LIMIT);
+
+        self.buffer.extend(run.as_bytes());
+        self.trace_text(run);
+        self.track_header_start(run.as_bytes());
+
+        if run.bytes().any(|bch| bch != b' ' && bch != b'\t') {
+            self.content_since_fws = true;
         }
-        //TODO I think I wrote a iterator for this somewhere
-        let mut start = 0;
-        // the first byte is never a continuation byte so we start
-        // scanning at the second byte
-        for (idx_m1, bch) in s.as_bytes()[1..].iter().enumerate() {
-            if !is_utf8_continuation_byte(*bch) {
-                // the idx is 1 smaller then it should so add 1
-                let end = idx_m1 + 1;
-                self.internal_write_char(&s[start..end])?;
-                start = end;
+    }
+
+    /// Removes the WS written after the colon of a header with an empty value.
+    fn strip_ws_after_colon(&mut self) {
+        debug_assert!(self.header_start == HeaderStart::AfterSeparator);
+        // AfterSeparator implies that there is no line break after the colon
+        // and that everything after it is WS
+        while let Some(&last) = self.buffer.last() {
+            if last == b' ' || last == b'\t' {
+                self.buffer.pop();
+            } else {
+                break;
             }
         }
+        self.last_fws_idx = cmp::min(self.last_fws_idx, self.buffer.len());
+    }
 
-        //write last letter
-        self.internal_write_char(&s[start..])?;
-        Ok(())
+    /// Updates `header_start` based on the bytes which where just written.
+    ///
+    /// Only the bytes written directly after `write_header_name` are
+    /// looked at, everything else is treated as part of a header value.
+    fn track_header_start(&mut self, written: &[u8]) {
+        if self.header_start != HeaderStart::AfterSeparator {
+            return;
+        }
+        for &bch in written {
+            match bch {
+                b' ' | b'\t' => debug_assert!(false,
+                    "header value written after `write_header_name` starts with WS"),
+                _ => {
+                    self.header_start = HeaderStart::Value;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn non_ascii_error(&self, bch: u8) -> EncodingError {
+        let header = &self.buffer[self.header_start_idx..];
+        let header_name = match header.iter().position(|&b| b == b':') {
+            Some(colon_idx) => format!("header {:?}", String::from_utf8_lossy(&header[..colon_idx])),
+            None => "partial header".to_owned()
+        };
+        let mut err = EncodingError::from((
+            EncodingErrorKind::InvalidTextEncoding {
+                expected_encoding: US_ASCII,
+                got_encoding: UTF_8
+            },
+            self.mail_type()
+        ));
+        err.set_str_context(format!(
+            "non us-ascii byte 0x{:02X} at offset {} in {}", bch, header.len(), header_name));
+        err
+    }
+
+    #[cfg(feature="traceing")]
+    fn trace_text(&mut self, text: &str) {
+        //FIXME[rust/nll]: just use a `if let`-`else` with NLL's
+        let need_new =
+            if let Some(&mut TraceToken::Text(ref mut string)) = self.trace.last_mut() {
+                string.push_str(text);
+                false
+            } else {
+                true
+            };
+        if need_new {
+            self.trace.push(TraceToken::Text(text.to_owned()))
+        }
     }
 
+    #[cfg(not(feature="traceing"))]
+    #[inline(always)]
+    fn trace_text(&mut self, _text: &str) {}
+
     /// if the line has at last one non-WS char a new line
-    /// will be started by adding `\r\n` if the current line
+    /// will be started by adding the line ending (`\r\n`) if the current line
     /// only consists of WS then a new line will be started by
     /// removing the blank line (not that WS are only ' ' and '\r')
     fn start_new_line(&mut self) {
@@ -624,8 +1148,7 @@ impl<'inner> EncodingWriter<'inner> {
             #[cfg(feature="traceing")]
             { self.trace.push(TraceToken::CRLF) }
 
-            self.buffer.push(b'\r');
-            self.buffer.push(b'\n');
+            self.buffer.extend(self.line_ending.as_str().as_bytes());
         } else {
             #[cfg(feature="traceing")]
             {
@@ -643,6 +1166,7 @@ impl<'inner> EncodingWriter<'inner> {
         self.content_since_fws = false;
         self.content_before_fws = false;
         self.last_fws_idx = self.line_start_idx;
+        self.header_start = HeaderStart::Value;
 
     }
 
@@ -650,13 +1174,15 @@ impl<'inner> EncodingWriter<'inner> {
         if self.content_before_fws && self.last_fws_idx > self.line_start_idx {
             //INDEX_SAFE: self.content_before_fws is only true if there is at last one char
             // if so self.last_ws_idx does not point at the end of the buffer but inside
-            let newline = match self.buffer[self.last_fws_idx] {
-                b' ' | b'\t' => "\r\n",
-                _ => "\r\n "
+            let line_ending = self.line_ending.as_str();
+            let newline = match (self.buffer[self.last_fws_idx], self.line_ending) {
+                (b' ', _) | (b'\t', _) => line_ending,
+                (_, LineEnding::CrLf) => "\r\n ",
+                (_, LineEnding::Lf) => "\n "
             };
 
             vec_insert_bytes(&mut self.buffer, self.last_fws_idx, newline.as_bytes());
-            self.line_start_idx = self.last_fws_idx + 2;
+            self.line_start_idx = self.last_fws_idx + line_ending.len();
             // no need last_fws can be < line_start but
             //self.last_fws_idx = self.line_start_idx;
             self.content_before_fws = false;
@@ -713,9 +1239,19 @@ impl<'inner> EncodingWriter<'inner> {
             }
         }
 
-        if self.current_line_byte_length() >= LINE_LEN_SOFT_LIMIT {
+        // last line of defense against components which forgot
+        // to check if they are allowed to write non us-ascii text
+        if bch >= 0x80 && !self.mail_type().is_internationalized() {
+            return Err(self.non_ascii_error(bch));
+        }
+
+        if self.folding_mode == FoldingMode::Fold
+            && self.current_line_byte_length() >= LINE_LEN_SOFT_LIMIT
+        {
             if !self.break_line_on_fws() {
-                if self.buffer.len() == LINE_LEN_HARD_LIMIT {
+                let new_line_len = self.current_line_byte_length()
+                    .saturating_add(unchecked_utf8_char.len());
+                if new_line_len > LINE_LEN_HARD_LIMIT {
                     ec_bail!(
                         mail_type: self.mail_type(),
                         kind: HardLineLengthLimitBreached
@@ -725,23 +1261,8 @@ impl<'inner> EncodingWriter<'inner> {
         }
 
         self.buffer.extend(unchecked_utf8_char.as_bytes());
-        #[cfg(feature="traceing")]
-        {
-            //FIXME[rust/nll]: just use a `if let`-`else` with NLL's
-            let need_new =
-                if let Some(&mut TraceToken::Text(ref mut string)) = self.trace.last_mut() {
-                    string.push_str(unchecked_utf8_char);
-                    false
-                } else {
-                    true
-                };
-            if need_new {
-                let mut string = String::new();
-                string.push_str(unchecked_utf8_char);
-                self.trace.push(TraceToken::Text(string))
-            }
-
-        }
+        self.trace_text(unchecked_utf8_char);
+        self.track_header_start(unchecked_utf8_char.as_bytes());
 
         // we can't allow "blank" lines
         if bch != b' ' && bch != b'\t' {
@@ -753,9 +1274,78 @@ impl<'inner> EncodingWriter<'inner> {
     }
 }
 
+/// The maximal length of a decimal `u64` or `i64` (`u64::MAX` has 20 digits).
+const MAX_DECIMAL_LEN: usize = 21;
+
+/// Writes the decimal representation of the number to the end of
+/// `buf` and returns the index at which it starts.
+fn format_decimal(mut number: u64, negative: bool, buf: &mut [u8; MAX_DECIMAL_LEN]) -> usize {
+    let mut idx = buf.len();
+    loop {
+        idx -= 1;
+        buf[idx] = b'0' + (number % 10) as u8;
+        number /= 10;
+        if number == 0 {
+            break;
+        }
+    }
+    if negative {
+        idx -= 1;
+        buf[idx] = b'-';
+    }
+    idx
+}
+
+/// True for bytes which can not be bulk written (see `internal_write_str`).
+///
+/// This are `'\r'`, `'\n'` and all bytes of non us-ascii chars.
+#[inline]
+fn needs_char_wise_handling(bch: u8) -> bool {
+    bch == b'\r' || bch == b'\n' || bch >= 0x80
+}
+
+/// Tracks the start of a header written with `write_header_name`, as no
+/// soft line break is allowed before the first char of the header value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HeaderStart {
+    /// directly after the separator written by `write_header_name`
+    AfterSeparator,
+    /// writing the value (or something which was not started with
+    /// `write_header_name`)
+    Value
+}
+
+/// Snapshot of the state of an `EncodingWriter` (see `EncodingWriter::mark`).
+struct WriterMark {
+    buffer_len: usize,
+    line_start_idx: usize,
+    last_fws_idx: usize,
+    breakable_fws_byte: Option<u8>,
+    blank_line_tail: Option<Vec<u8>>,
+    skipped_cr: bool,
+    content_since_fws: bool,
+    content_before_fws: bool,
+    header_start: HeaderStart,
+    #[cfg(feature="traceing")]
+    trace_len: usize
+}
+
+/// The result of a conditional write (e.g. `EncodingWriter::write_if_utf8`).
+///
+/// Conditional writes are transactional: Nothing is written if the
+/// condition fails (`ConditionFailure`) and if the write itself fails
+/// (`GeneralFailure`) everything written by it is rolled back, including
+/// soft line breaks it inserted. The same is true for the fallback passed
+/// to `handle_condition_failure`, i.e. if it returns an error all writes
+/// it did are rolled back before the error is returned.
+///
+/// Writes done _before_ the conditional write are never affected.
 pub enum ConditionalWriteResult<'a, 'b: 'a> {
+    /// The condition was met and the text was written.
     Ok,
+    /// The condition was not met, nothing was written.
     ConditionFailure(&'a mut EncodingWriter<'b>),
+    /// The condition was met, but writing failed (and was rolled back).
     GeneralFailure(EncodingError)
 }
 
@@ -770,6 +1360,9 @@ impl<'a, 'b: 'a> From<Result<(), EncodingError>> for ConditionalWriteResult<'a,
 
 impl<'a, 'b: 'a> ConditionalWriteResult<'a, 'b> {
 
+    /// calls `func` with the writer if the condition failed
+    ///
+    /// If `func` fails all writes done by it are rolled back.
     #[inline]
     pub fn handle_condition_failure<FN>(self, func: FN) -> Result<(), EncodingError>
         where FN: FnOnce(&mut EncodingWriter) -> Result<(), EncodingError>
@@ -779,7 +1372,7 @@ impl<'a, 'b: 'a> ConditionalWriteResult<'a, 'b> {
         match self {
             CWR::Ok => Ok(()),
             CWR::ConditionFailure(handle) => {
-                func(handle)
+                handle.transaction(|handle| func(handle))
             },
             CWR::GeneralFailure(err) => Err(err)
         }
@@ -798,7 +1391,10 @@ mod test {
     use ::error::EncodingErrorKind;
 
     use super::TraceToken::*;
-    use super::{EncodingBuffer as _Encoder};
+    use super::{
+        EncodingBuffer as _Encoder, LineEnding, FoldingMode,
+        NameValueSeparator, LINE_LEN_HARD_LIMIT, LINE_LEN_SOFT_LIMIT
+    };
 
     mod test_test_utilities {
         use encoder::TraceToken::*;
@@ -918,6 +1514,39 @@ mod test {
         use super::*;
         use super::{ _Encoder as EncodingBuffer };
 
+        #[test]
+        fn reset_does_not_leak_state() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X-A: abc")));
+                handle.finish_header();
+            }
+            let capacity = encoder.buffer.capacity();
+
+            encoder.reset(MailType::Internationalized);
+            assert_eq!(encoder.mail_type(), MailType::Internationalized);
+            assert_eq!(encoder.as_slice(), b"");
+            assert_eq!(encoder.trace, vec![]);
+            assert_eq!(encoder.buffer.capacity(), capacity);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_utf8("X-B: ↓"));
+                handle.finish_header();
+            }
+            assert_eq!(encoder.as_str().unwrap(), "X-B: ↓\r\n");
+
+            encoder.reset(MailType::Ascii);
+            assert_eq!(encoder.as_slice(), b"");
+            {
+                let mut handle = encoder.writer();
+                assert_err!(handle.write_utf8("↓"));
+                handle.undo_header();
+                assert_eq!(handle.current_line_byte_length(), 0);
+            }
+            assert_eq!(encoder.trace, vec![]);
+        }
+
         #[test]
         fn new_encoder() {
             let encoder = EncodingBuffer::new(MailType::Internationalized);
@@ -927,6 +1556,7 @@ mod test {
         #[test]
         fn write_body_unchecked() {
             let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_eq!(encoder.line_ending(), LineEnding::CrLf);
             let body1 = "una body\r\n";
             let body2 = "another body";
 
@@ -1241,33 +1871,396 @@ mod test {
         }
 
         #[test]
-        fn hard_line_limit() {
+        fn no_fold_between_colon_and_value() {
             let mut encoder = EncodingBuffer::new(MailType::Ascii);
             {
                 let mut handle = encoder.writer();
-                for x in 0..998 {
-                    if let Err(_) = handle.write_char(SoftAsciiChar::from_unchecked('X')) {
-                        panic!("error when writing char nr.: {:?}", x+1)
-                    }
-                }
-                let res = &[
-                    handle.write_char(SoftAsciiChar::from_unchecked('X')).is_err(),
-                    handle.write_char(SoftAsciiChar::from_unchecked('X')).is_err(),
-                    handle.write_char(SoftAsciiChar::from_unchecked('X')).is_err(),
-                    handle.write_char(SoftAsciiChar::from_unchecked('X')).is_err(),
-                ];
-                assert_eq!(
-                    res, &[true, true, true, true]
-                );
-                handle.undo_header();
+                assert_ok!(handle.write_header_name(SoftAsciiStr::from_unchecked("A23456789")));
+                handle.mark_fws_pos();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_str(concat!(
+                    "20_3456789",
+                    "30_3456789",
+                    "40_3456789",
+                    "50_3456789",
+                    "60_3456789",
+                    "70_3456789",
+                    "12345678XX"
+                )).unwrap()));
+                handle.finish_header();
             }
-        }
-
-        #[test]
-        fn write_utf8_fail_on_ascii_mail() {
-            let mut encoder = EncodingBuffer::new(MailType::Ascii);
-            {
-                let mut handle = encoder.writer();
+            assert_eq!(
+                encoder.as_str().unwrap(),
+                concat!(
+                    "A23456789: ",
+                    "20_3456789",
+                    "30_3456789",
+                    "40_3456789",
+                    "50_3456789",
+                    "60_3456789",
+                    "70_3456789",
+                    "12345678XX\r\n"
+                )
+            );
+        }
+
+        #[test]
+        fn long_header_name_and_long_first_token() {
+            let name = concat!(
+                "X-o_3456789",
+                "20_3456789",
+                "30_3456789",
+                "40_3456789",
+                "50_3456789",
+                "60_345678"
+            );
+            let token = concat!(
+                "10_3456789",
+                "20_3456789",
+                "30_3456789",
+                "40_3456789",
+                "50_3456789",
+                "60_3456789"
+            );
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_header_name(SoftAsciiStr::from_str(name).unwrap()));
+                assert_ok!(handle.write_str(SoftAsciiStr::from_str(token).unwrap()));
+                handle.write_fws();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_str("next").unwrap()));
+                handle.finish_header();
+            }
+            assert_eq!(
+                encoder.as_str().unwrap(),
+                format!("{}: {}\r\n next\r\n", name, token)
+            );
+        }
+
+        #[test]
+        fn no_empty_first_line_if_fws_is_marked_after_header_name() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_header_name(SoftAsciiStr::from_str(concat!(
+                    "X-o_3456789",
+                    "20_3456789",
+                    "30_3456789",
+                    "40_3456789",
+                    "50_3456789",
+                    "60_3456789",
+                    "70_345678"
+                )).unwrap()));
+                handle.mark_fws_pos();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_str("value").unwrap()));
+                handle.finish_header();
+            }
+            let out = encoder.as_str().unwrap();
+            assert!(out.ends_with(": value\r\n"), "{:?}", out);
+            assert_eq!(out.matches("\r\n").count(), 1);
+        }
+
+        #[test]
+        fn never_fold_does_not_break_lines() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            encoder.set_folding_mode(FoldingMode::NeverFold);
+            let mut expected = String::from("X-Long: a");
+            {
+                let mut handle = encoder.writer();
+                assert_eq!(handle.folding_mode(), FoldingMode::NeverFold);
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X-Long: a")));
+                for _ in 0..150 {
+                    handle.write_fws();
+                    assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("10_3456789")));
+                    expected.push_str(" 10_3456789");
+                }
+                handle.finish_header();
+            }
+            expected.push_str("\r\n");
+            assert!(expected.len() > LINE_LEN_HARD_LIMIT);
+            assert_eq!(encoder.as_str().unwrap(), expected);
+        }
+
+        #[test]
+        fn empty_value_has_no_trailing_space() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_header_name(SoftAsciiStr::from_unchecked("Comments")));
+                handle.mark_fws_pos();
+                handle.finish_header();
+                assert_ok!(handle.write_header_name(SoftAsciiStr::from_unchecked("Bcc")));
+                handle.finish_header();
+                assert_ok!(handle.write_header_name(SoftAsciiStr::from_unchecked("X-Flag")));
+                handle.finish_header();
+            }
+            assert_eq!(encoder.as_str().unwrap(), "Comments:\r\nBcc:\r\nX-Flag:\r\n");
+        }
+
+        #[test]
+        fn trailing_space_is_kept_for_non_empty_values() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_header_name(SoftAsciiStr::from_unchecked("Comments")));
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("a ")));
+                handle.finish_header();
+            }
+            assert_eq!(encoder.as_str().unwrap(), "Comments: a \r\n");
+        }
+
+        #[test]
+        fn colon_in_component_on_fresh_writer_does_not_prevent_folding() {
+            let token = concat!(
+                "10_3456789",
+                "20_3456789",
+                "30_3456789",
+                "40_3456789",
+                "50_3456789",
+                "60_3456789",
+                "70_3456"
+            );
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("a:b")));
+                handle.write_fws();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked(token)));
+                handle.finish_header();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("a:")));
+                handle.write_fws();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked(token)));
+                handle.finish_header();
+            }
+            assert_eq!(
+                encoder.as_str().unwrap(),
+                format!("a:b\r\n {}\r\na:\r\n {}\r\n", token, token)
+            );
+        }
+
+        #[test]
+        fn write_numbers() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_u64(0));
+                handle.write_fws();
+                assert_ok!(handle.write_u64(::std::u64::MAX));
+                handle.write_fws();
+                assert_ok!(handle.write_usize(1234));
+                handle.write_fws();
+                assert_ok!(handle.write_i64(-2));
+                handle.write_fws();
+                assert_ok!(handle.write_i64(::std::i64::MIN));
+                handle.write_fws();
+                assert_ok!(handle.write_i64(::std::i64::MAX));
+                handle.commit_partial_header();
+            }
+            assert_eq!(encoder.as_str().unwrap(), concat!(
+                "0 18446744073709551615 1234 -2 ",
+                "-9223372036854775808 9223372036854775807"
+            ));
+        }
+
+        #[test]
+        fn numbers_count_towards_the_line_length() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X-Size: 1")));
+                assert_ok!(handle.write_u64(2345));
+                assert_eq!(handle.current_line_byte_length(), 13);
+                handle.finish_header();
+            }
+            assert_eq!(encoder.as_str().unwrap(), "X-Size: 12345\r\n");
+        }
+
+        #[test]
+        fn write_header_name_writes_a_single_separator() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                let headers = &[
+                    ("Subject", "hy there"),
+                    ("From", "<a@b.example>"),
+                    ("Content-Type", "text/plain"),
+                    ("Message-Id", "<1@b.example>")
+                ];
+                for &(name, value) in headers.iter() {
+                    assert_ok!(handle.write_header_name(SoftAsciiStr::from_unchecked(name)));
+                    assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked(value)));
+                    handle.finish_header();
+                }
+            }
+            assert_eq!(encoder.as_str().unwrap(), concat!(
+                "Subject: hy there\r\n",
+                "From: <a@b.example>\r\n",
+                "Content-Type: text/plain\r\n",
+                "Message-Id: <1@b.example>\r\n"
+            ));
+        }
+
+        #[test]
+        fn write_header_name_with_tab_separator() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            encoder.set_name_value_separator(NameValueSeparator::Tab);
+            {
+                let mut handle = encoder.writer();
+                assert_eq!(handle.name_value_separator(), NameValueSeparator::Tab);
+                assert_ok!(handle.write_header_name(SoftAsciiStr::from_unchecked("Subject")));
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("a")));
+                handle.finish_header();
+            }
+            assert_eq!(encoder.as_str().unwrap(), "Subject:\ta\r\n");
+        }
+
+        #[test]
+        fn write_header_name_with_empty_value() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_header_name(SoftAsciiStr::from_unchecked("Comments")));
+                handle.finish_header();
+            }
+            assert_eq!(encoder.as_str().unwrap(), "Comments:\r\n");
+        }
+
+        #[test]
+        fn write_header_name_rejects_invalid_names() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                let err = assert_err!(handle.write_header_name(SoftAsciiStr::from_unchecked("")));
+                assert_eq!(err.input_offset(), Some(0));
+                let err = assert_err!(handle.write_header_name(SoftAsciiStr::from_unchecked("Sub ject")));
+                assert_eq!(err.input_offset(), Some(3));
+                let err = assert_err!(handle.write_header_name(SoftAsciiStr::from_unchecked("Subject:")));
+                assert_eq!(err.input_offset(), Some(7));
+                assert_not!(handle.has_unfinished_parts());
+            }
+            assert_eq!(encoder.as_slice(), b"");
+        }
+
+        #[cfg(debug_assertions)]
+        #[test]
+        #[should_panic]
+        fn value_starting_with_ws_after_write_header_name_panics() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            let mut handle = encoder.writer();
+            assert_ok!(handle.write_header_name(SoftAsciiStr::from_unchecked("Subject")));
+            handle.write_fws();
+        }
+
+        #[test]
+        fn hard_line_limit() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                for x in 0..998 {
+                    if let Err(_) = handle.write_char(SoftAsciiChar::from_unchecked('X')) {
+                        panic!("error when writing char nr.: {:?}", x+1)
+                    }
+                }
+                let res = &[
+                    handle.write_char(SoftAsciiChar::from_unchecked('X')).is_err(),
+                    handle.write_char(SoftAsciiChar::from_unchecked('X')).is_err(),
+                    handle.write_char(SoftAsciiChar::from_unchecked('X')).is_err(),
+                    handle.write_char(SoftAsciiChar::from_unchecked('X')).is_err(),
+                ];
+                assert_eq!(
+                    res, &[true, true, true, true]
+                );
+                handle.undo_header();
+            }
+        }
+
+        #[test]
+        fn hard_line_limit_is_per_line() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X-A: b")));
+                handle.finish_header();
+                for x in 0..998 {
+                    if let Err(_) = handle.write_char(SoftAsciiChar::from_unchecked('X')) {
+                        panic!("error when writing char nr.: {:?}", x+1)
+                    }
+                }
+                assert_eq!(handle.current_line_byte_length(), 998);
+                assert_err!(handle.write_char(SoftAsciiChar::from_unchecked('X')));
+                handle.undo_header();
+            }
+            assert_eq!(encoder.as_slice(), b"X-A: b\r\n");
+        }
+
+        #[test]
+        fn hard_line_limit_counts_bytes_of_multi_byte_chars() {
+            let mut encoder = EncodingBuffer::new(MailType::Internationalized);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked(&"X".repeat(996))));
+                // "ä" is 2 bytes, so it fits exactly
+                assert_ok!(handle.write_utf8("ä"));
+                assert_eq!(handle.current_line_byte_length(), 998);
+                handle.undo_header();
+
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked(&"X".repeat(997))));
+                // would straddle the limit by one byte
+                assert_err!(handle.write_utf8("ä"));
+                assert_eq!(handle.current_line_byte_length(), 997);
+                handle.undo_header();
+            }
+        }
+
+        #[test]
+        fn line_length_is_restored_by_rollback() {
+            let mut encoder = EncodingBuffer::new(MailType::Internationalized);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked(&"X".repeat(990))));
+                assert_err!(handle.write_if_utf8(&"ü".repeat(5))
+                    .handle_condition_failure(|_| panic!("unexpected condition failure")));
+                assert_eq!(handle.current_line_byte_length(), 990);
+                assert_ok!(handle.write_utf8(&"ü".repeat(4)));
+                assert_eq!(handle.current_line_byte_length(), 998);
+                assert_err!(handle.write_char(SoftAsciiChar::from_unchecked('X')));
+                handle.undo_header();
+            }
+        }
+
+        #[test]
+        fn fold_exactly_at_soft_limit() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("A23456789: a")));
+                handle.write_fws();
+                let rest = "X".repeat(LINE_LEN_SOFT_LIMIT - handle.current_line_byte_length());
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked(&rest)));
+                assert_eq!(handle.current_line_byte_length(), LINE_LEN_SOFT_LIMIT);
+                handle.finish_header();
+            }
+            // the line has exactly the soft limit, so it is not folded
+            assert_eq!(encoder.as_str().unwrap().matches("\r\n").count(), 1);
+
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("A23456789: a")));
+                handle.write_fws();
+                let rest = "X".repeat(LINE_LEN_SOFT_LIMIT + 1 - handle.current_line_byte_length());
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked(&rest)));
+                handle.finish_header();
+            }
+            // one char more and it is folded at the fws
+            let out = encoder.as_str().unwrap();
+            assert!(out.starts_with("A23456789: a\r\n X"), "{:?}", out);
+        }
+
+        #[test]
+        fn write_utf8_fail_on_ascii_mail() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
                 assert_err!(handle.write_utf8("↓"));
                 handle.undo_header();
             }
@@ -1602,6 +2595,271 @@ mod test {
             ]);
             assert_eq!(encoder.as_slice(), format!("  {}\r\n", long_line).as_bytes())
         }
+
+        #[test]
+        fn lf_line_ending_is_used_everywhere() {
+            let mut encoder = EncodingBuffer::with_line_ending(MailType::Ascii, LineEnding::Lf);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked(concat!(
+                    "10_3456789",
+                    "20_3456789",
+                    "30_3456789",
+                    "40_3456789",
+                    "50_3456789",
+                    "60_3456789",
+                    "70_3456789",
+                ))));
+                handle.write_fws();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("abcdefghij")));
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("\r\n next\r\n")));
+                handle.finish_header();
+            }
+            encoder.write_blank_line();
+            encoder.write_body_unchecked(&"body");
+
+            let out = encoder.as_str().unwrap();
+            assert!(!out.contains('\r'), "{:?}", out);
+            assert_eq!(out, concat!(
+                "10_3456789",
+                "20_3456789",
+                "30_3456789",
+                "40_3456789",
+                "50_3456789",
+                "60_3456789",
+                "70_3456789\n",
+                " abcdefghij\n",
+                " next\n",
+                "\n",
+                "body\n"
+            ));
+        }
+
+        #[test]
+        fn lf_line_ending_break_without_whitespace() {
+            let mut encoder = EncodingBuffer::with_line_ending(MailType::Ascii, LineEnding::Lf);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("A234567890")));
+                handle.mark_fws_pos();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked(concat!(
+                    "20_3456789",
+                    "30_3456789",
+                    "40_3456789",
+                    "50_3456789",
+                    "60_3456789",
+                    "70_3456789",
+                    "12345678XX"
+                ))));
+                handle.finish_header();
+            }
+            assert_eq!(encoder.as_str().unwrap(), concat!(
+                "A234567890\n ",
+                "20_3456789",
+                "30_3456789",
+                "40_3456789",
+                "50_3456789",
+                "60_3456789",
+                "70_3456789",
+                "12345678XX\n"
+            ));
+        }
+
+        #[test]
+        fn lf_line_length_does_not_include_line_ending() {
+            let mut encoder = EncodingBuffer::with_line_ending(MailType::Ascii, LineEnding::Lf);
+            {
+                let mut handle = encoder.writer();
+                for _ in 0..998 {
+                    assert_ok!(handle.write_char(SoftAsciiChar::from_unchecked('X')));
+                }
+                assert_eq!(handle.current_line_byte_length(), 998);
+                assert_err!(handle.write_char(SoftAsciiChar::from_unchecked('X')));
+                handle.undo_header();
+            }
+        }
+
+        fn write_char_wise(handle: &mut ::encoder::EncodingWriter, input: &str)
+            -> Result<(), ::error::EncodingError>
+        {
+            for ch in input.chars() {
+                if ch == ' ' {
+                    handle.write_fws();
+                } else {
+                    let mut buf = [0u8; 4];
+                    handle.write_utf8(ch.encode_utf8(&mut buf))?;
+                }
+            }
+            Ok(())
+        }
+
+        fn write_bulk(handle: &mut ::encoder::EncodingWriter, input: &str)
+            -> Result<(), ::error::EncodingError>
+        {
+            for (idx, part) in input.split(' ').enumerate() {
+                if idx > 0 {
+                    handle.write_fws();
+                }
+                handle.write_utf8(part)?;
+            }
+            Ok(())
+        }
+
+        #[test]
+        fn bulk_write_matches_char_wise_write() {
+            let inputs = &[
+                "",
+                "abc",
+                "ab\r\n cd",
+                "ä ö ü abc\r\n\r\n def",
+                concat!(
+                    "10_3456789 20_3456789 30_3456789 40_3456789 50_3456789 ",
+                    "60_3456789 70_3456789 80_3456789 90_3456789 ",
+                    "ab 12345678901234567890123456789012345678901234567890",
+                    "1234567890123456789012345678901234567890 x\r\n y"
+                ),
+                concat!(
+                    "ääääääääää ääääääääää ääääääääää ääääääääää ääääääääää ",
+                    "ääääääääää ääääääääää ääääääääää"
+                )
+            ];
+
+            for input in inputs {
+                let mut bulk = EncodingBuffer::new(MailType::Internationalized);
+                let mut char_wise = EncodingBuffer::new(MailType::Internationalized);
+                {
+                    let mut handle = bulk.writer();
+                    assert_ok!(write_bulk(&mut handle, input));
+                    handle.finish_header();
+                }
+                {
+                    let mut handle = char_wise.writer();
+                    assert_ok!(write_char_wise(&mut handle, input));
+                    handle.finish_header();
+                }
+                assert_eq!(bulk.as_str().unwrap(), char_wise.as_str().unwrap());
+            }
+        }
+
+        #[test]
+        fn bulk_write_still_detects_orphan_cr() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_err!(handle.write_str(SoftAsciiStr::from_unchecked("ab\rcd")));
+                handle.undo_header();
+            }
+            assert_eq!(encoder.as_slice(), b"");
+        }
+
+        #[test]
+        fn write_if_utf8_rolls_back_on_general_failure() {
+            let mut encoder = EncodingBuffer::new(MailType::Internationalized);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_utf8("abc"));
+                assert_err!(handle.write_if_utf8("de\nf")
+                    .handle_condition_failure(|_| panic!("unexpected condition failure")));
+                handle.commit_partial_header();
+            }
+            assert_eq!(encoder.as_slice(), b"abc");
+            assert_eq!(encoder.trace, vec![
+                NowUtf8,
+                Text("abc".into()),
+                End
+            ]);
+        }
+
+        #[test]
+        fn failing_fallback_is_rolled_back() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("abc")));
+                assert_err!(handle.write_if_utf8("tüxt")
+                    .handle_condition_failure(|handle| {
+                        handle.write_str(SoftAsciiStr::from_unchecked("partial"))?;
+                        Err(EncodingErrorKind::Other { kind: "fallback failed" }.into())
+                    }));
+                handle.commit_partial_header();
+            }
+            assert_eq!(encoder.as_slice(), b"abc");
+            assert_eq!(encoder.trace, vec![
+                NowStr,
+                Text("abc".into()),
+                End
+            ]);
+        }
+
+        #[test]
+        fn successful_fallback_is_kept() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_if_utf8("tüxt")
+                    .handle_condition_failure(|handle| {
+                        handle.write_str(SoftAsciiStr::from_unchecked("fallback"))
+                    }));
+                handle.commit_partial_header();
+            }
+            assert_eq!(encoder.as_slice(), b"fallback");
+        }
+
+        #[test]
+        fn rollback_removes_inserted_soft_line_break() {
+            let start = concat!(
+                "10_3456789",
+                "20_3456789",
+                "30_3456789",
+                "40_3456789",
+                "50_3456789",
+                "60_3456789",
+                "70_3456789",
+            );
+            let mut encoder = EncodingBuffer::new(MailType::Internationalized);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_utf8(start));
+                handle.write_fws();
+                assert_ok!(handle.write_utf8("abc"));
+                // this will insert a soft line break at the fws and then fail
+                assert_err!(handle.write_if_utf8("0123456789\n")
+                    .handle_condition_failure(|_| panic!("unexpected condition failure")));
+                assert_ok!(handle.write_utf8("de"));
+                handle.finish_header();
+            }
+            assert_eq!(
+                encoder.as_str().unwrap(),
+                format!("{} abcde\r\n", start)
+            );
+        }
+
+        #[test]
+        fn rollback_restores_blank_line() {
+            let mut encoder = EncodingBuffer::new(MailType::Internationalized);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_utf8("a\r\n  "));
+                // this would truncate the blank line and then fail
+                assert_err!(handle.write_if_utf8("\r\nb\n")
+                    .handle_condition_failure(|_| panic!("unexpected condition failure")));
+                handle.commit_partial_header();
+            }
+            assert_eq!(encoder.as_slice(), b"a\r\n  ");
+        }
+
+        #[test]
+        fn write_if_rolls_back_on_general_failure() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("abc")));
+                assert_err!(handle.write_if("de\rf", |_| true)
+                    .handle_condition_failure(|_| panic!("unexpected condition failure")));
+                handle.commit_partial_header();
+            }
+            assert_eq!(encoder.as_slice(), b"abc");
+        }
     }
 
     ec_test! {
@@ -1644,6 +2902,8 @@ mod test {
     }
 
     mod trait_object {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
         use super::super::*;
 
         #[derive(Default, Clone, PartialEq, Debug)]
@@ -1689,6 +2949,19 @@ mod test {
             assert_eq!( None, erased.downcast_ref::<AnotherType>() );
         }
 
+        #[test]
+        fn downcast_checked() {
+            let tt = TestType::default();
+            let erased: &EncodableInHeader = &tt;
+            assert_eq!( Ok(&tt), erased.downcast_checked::<TestType>() );
+
+            let err = assert_err!(erased.downcast_checked::<AnotherType>());
+            assert!(err.expected.ends_with("AnotherType"), "{:?}", err);
+            assert!(err.actual.ends_with("TestType"), "{:?}", err);
+            // explicitly skipping a mismatch
+            assert_eq!( None, erased.downcast_checked::<AnotherType>().ok() );
+        }
+
         #[test]
         fn downcast_mut() {
             let mut tt_nr2 = TestType::default();
@@ -1701,6 +2974,171 @@ mod test {
             assert_eq!( None, erased.downcast_mut::<AnotherType>() );
         }
 
+        #[derive(Clone, Debug)]
+        struct LongToken(usize);
+
+        impl EncodableInHeader for LongToken {
+            fn encode(&self, encoder:  &mut EncodingWriter) -> Result<(), EncodingError> {
+                for _ in 0..self.0 {
+                    encoder.write_char(SoftAsciiChar::from_unchecked('x'))?;
+                }
+                Ok(())
+            }
+
+            fn boxed_clone(&self) -> Box<EncodableInHeader> {
+                Box::new(self.clone())
+            }
+
+            fn max_unbreakable_run(&self) -> Option<usize> {
+                Some(self.0)
+            }
+
+            fn encoded_size_hint(&self) -> (usize, Option<usize>) {
+                (self.0, Some(self.0))
+            }
+        }
+
+        #[test]
+        fn check_unbreakable_run_default_is_unknown() {
+            let tt = TestType::default();
+            assert_eq!(tt.max_unbreakable_run(), None);
+            assert_ok!(check_unbreakable_run(&tt, 900));
+        }
+
+        /// A component which "forgets" to check the mail type.
+        #[derive(Clone, Debug)]
+        struct BrokenUtf8Component(&'static str);
+
+        impl EncodableInHeader for BrokenUtf8Component {
+            fn encode(&self, encoder:  &mut EncodingWriter) -> Result<(), EncodingError> {
+                encoder.write_str_unchecked(self.0)
+            }
+
+            fn boxed_clone(&self) -> Box<EncodableInHeader> {
+                Box::new(self.clone())
+            }
+        }
+
+        fn encode_broken_header(mail_type: MailType) -> (Result<(), EncodingError>, EncodingBuffer) {
+            let mut encoder = EncodingBuffer::new(mail_type);
+            let res = encoder.write_header_line(|handle| {
+                handle.write_str(SoftAsciiStr::from_unchecked("X-Broken: "))?;
+                BrokenUtf8Component("ab↓c").encode(handle)
+            });
+            (res, encoder)
+        }
+
+        #[test]
+        fn non_ascii_bytes_are_rejected_for_ascii_mails() {
+            for &mail_type in &[MailType::Ascii, MailType::Mime8BitEnabled] {
+                let (res, encoder) = encode_broken_header(mail_type);
+                let err = assert_err!(res);
+                assert_eq!(err.kind(), EncodingErrorKind::InvalidTextEncoding {
+                    expected_encoding: US_ASCII,
+                    got_encoding: UTF_8
+                });
+                assert_eq!(
+                    err.str_context(),
+                    Some("non us-ascii byte 0xE2 at offset 12 in header \"X-Broken\""));
+                assert_eq!(encoder.as_slice(), b"");
+            }
+        }
+
+        #[test]
+        fn non_ascii_bytes_are_fine_for_internationalized_mails() {
+            let (res, encoder) = encode_broken_header(MailType::Internationalized);
+            assert_ok!(res);
+            assert_eq!(encoder.as_str().unwrap(), "X-Broken: ab↓c\r\n");
+        }
+
+        fn counting_lazy(counter: Arc<AtomicUsize>)
+            -> LazyComponent<TestType, impl Send + Sync + Fn() -> Result<TestType, EncodingError>>
+        {
+            LazyComponent::new(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(TestType("lazy"))
+            })
+        }
+
+        #[test]
+        fn lazy_component_is_computed_at_most_once() {
+            let counter = Arc::new(AtomicUsize::new(0));
+            let lazy = counting_lazy(counter.clone());
+            assert_eq!(counter.load(Ordering::SeqCst), 0);
+            assert!(!lazy.is_materialized());
+
+            for _ in 0..3 {
+                let mut encoder = EncodingBuffer::new(MailType::Internationalized);
+                assert_ok!(encoder.write_header_line(|handle| lazy.encode(handle)));
+                assert_eq!(encoder.as_str().unwrap(), "lazy\r\n");
+            }
+            assert_eq!(counter.load(Ordering::SeqCst), 1);
+
+            let clone = lazy.clone();
+            assert_eq!(assert_ok!(clone.force()), &TestType("lazy"));
+            assert_eq!(counter.load(Ordering::SeqCst), 1);
+        }
+
+        #[test]
+        fn lazy_component_can_be_downcast_after_materialization() {
+            let counter = Arc::new(AtomicUsize::new(0));
+            let lazy = counting_lazy(counter.clone());
+            let erased: &EncodableInHeader = &lazy;
+            assert_eq!(erased.downcast_ref::<TestType>(), None);
+            assert_eq!(
+                assert_ok!(erased.materialize_and_downcast_ref::<TestType>()),
+                Some(&TestType("lazy")));
+            assert_eq!(assert_ok!(erased.materialize_and_downcast_ref::<AnotherType>()), None);
+            assert_eq!(counter.load(Ordering::SeqCst), 1);
+        }
+
+        #[test]
+        fn failing_lazy_component_is_retried() {
+            let counter = Arc::new(AtomicUsize::new(0));
+            let counter2 = counter.clone();
+            let lazy = LazyComponent::new(move || {
+                if counter2.fetch_add(1, Ordering::SeqCst) == 0 {
+                    Err(EncodingError::from(EncodingErrorKind::Malformed))
+                } else {
+                    Ok(TestType("second"))
+                }
+            });
+            assert_err!(lazy.force());
+            assert_eq!(assert_ok!(lazy.force()), &TestType("second"));
+            assert_eq!(counter.load(Ordering::SeqCst), 2);
+        }
+
+        #[test]
+        fn encoded_size_hint_default_is_unknown() {
+            assert_eq!(TestType("abc").encoded_size_hint(), (0, None));
+        }
+
+        #[test]
+        fn lazy_component_forwards_size_hint_once_materialized() {
+            let lazy = LazyComponent::new(|| Ok(LongToken(12)));
+            assert_eq!(lazy.encoded_size_hint(), (0, None));
+            assert_ok!(lazy.force());
+            assert_eq!(lazy.encoded_size_hint(), (12, Some(12)));
+        }
+
+        #[test]
+        fn reserve_uses_size_hint() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            let (lower, _) = LongToken(200).encoded_size_hint();
+            encoder.reserve(lower);
+            assert!(encoder.buffer.capacity() >= 200);
+            assert_ok!(encoder.write_header_line(|handle| LongToken(200).encode(handle)));
+            assert!(encoder.as_slice().len() >= lower);
+        }
+
+        #[test]
+        fn check_unbreakable_run_fails_for_to_long_parts() {
+            let err = assert_err!(check_unbreakable_run(&LongToken(1200), 9));
+            assert_eq!(err.kind(), EncodingErrorKind::HardLineLengthLimitBreached);
+            assert_err!(check_unbreakable_run(&LongToken(990), 9));
+            assert_ok!(check_unbreakable_run(&LongToken(989), 9));
+        }
+
         #[test]
         fn downcast() {
             let tt = Box::new( TestType::default() );
@@ -0,0 +1,127 @@
+use std::sync::Mutex;
+
+use ::MailType;
+use super::{EncodingBuffer, LineEnding};
+
+/// A pool of `EncodingBuffer`s which can be reused to avoid reallocating
+/// the internal buffers each time a mail is encoded.
+///
+/// Buffers are taken out of the pool for the duration of a
+/// `with_encoder` call, so the pool can be shared between threads.
+#[derive(Default)]
+pub struct EncoderPool {
+    line_ending: LineEnding,
+    buffers: Mutex<Vec<EncodingBuffer>>
+}
+
+impl EncoderPool {
+
+    /// Creates a new empty pool which creates buffers using `LineEnding::CrLf`.
+    pub fn new() -> Self {
+        EncoderPool::with_line_ending(LineEnding::default())
+    }
+
+    /// Creates a new empty pool which creates buffers using the given line ending.
+    pub fn with_line_ending(line_ending: LineEnding) -> Self {
+        EncoderPool {
+            line_ending,
+            buffers: Mutex::new(Vec::new())
+        }
+    }
+
+    /// Returns the line ending of buffers created by this pool.
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    /// Calls `func` with a (reset) buffer for the given mail type.
+    ///
+    /// The buffer is taken from the pool, or created if the pool is empty,
+    /// and returned to the pool after `func` returned. As such any data
+    /// written to the buffer has to be used/copied inside of `func`.
+    ///
+    /// If `func` panics the buffer is not returned to the pool.
+    pub fn with_encoder<FN, R>(&self, mail_type: MailType, func: FN) -> R
+        where FN: FnOnce(&mut EncodingBuffer) -> R
+    {
+        let mut encoder = match self.take() {
+            Some(mut encoder) => {
+                encoder.reset(mail_type);
+                encoder
+            },
+            None => EncodingBuffer::with_line_ending(mail_type, self.line_ending)
+        };
+        let result = func(&mut encoder);
+        self.put_back(encoder);
+        result
+    }
+
+    /// Returns the number of buffers currently in the pool.
+    pub fn pooled_count(&self) -> usize {
+        self.lock().len()
+    }
+
+    fn take(&self) -> Option<EncodingBuffer> {
+        self.lock().pop()
+    }
+
+    fn put_back(&self, encoder: EncodingBuffer) {
+        self.lock().push(encoder)
+    }
+
+    fn lock(&self) -> ::std::sync::MutexGuard<Vec<EncodingBuffer>> {
+        // the lock is never held while calling user code, so
+        // poisoning can not leave the vec in a broken state
+        self.buffers.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use soft_ascii_string::SoftAsciiStr;
+
+    use ::MailType;
+    use super::EncoderPool;
+
+    #[test]
+    fn buffers_are_reused() {
+        let pool = EncoderPool::new();
+        assert_eq!(pool.pooled_count(), 0);
+        pool.with_encoder(MailType::Ascii, |encoder| {
+            encoder.write_header_line(|handle| {
+                handle.write_str(SoftAsciiStr::from_unchecked("X-A: abc"))
+            }).unwrap();
+        });
+        assert_eq!(pool.pooled_count(), 1);
+
+        pool.with_encoder(MailType::Ascii, |encoder| {
+            assert_eq!(encoder.as_slice(), b"");
+            assert!(encoder.buffer.capacity() > 0);
+        });
+        assert_eq!(pool.pooled_count(), 1);
+    }
+
+    #[test]
+    fn nested_use_creates_new_buffers() {
+        let pool = EncoderPool::new();
+        pool.with_encoder(MailType::Ascii, |_| {
+            pool.with_encoder(MailType::Ascii, |_| {});
+        });
+        assert_eq!(pool.pooled_count(), 2);
+    }
+
+    #[test]
+    fn mail_type_does_not_leak_between_uses() {
+        let pool = EncoderPool::new();
+        pool.with_encoder(MailType::Internationalized, |encoder| {
+            assert_eq!(encoder.mail_type(), MailType::Internationalized);
+        });
+        pool.with_encoder(MailType::Ascii, |encoder| {
+            assert_eq!(encoder.mail_type(), MailType::Ascii);
+            let mut handle = encoder.writer();
+            assert_err!(handle.write_utf8("↓"));
+            handle.undo_header();
+        });
+    }
+}
@@ -1,15 +1,26 @@
 //! Module containing the `EncodingError`.
+use std::convert::Infallible;
 use std::fmt::{self, Display};
 
 use failure::{Context, Fail, Backtrace};
 use ::MailType;
+use ::utils::text::truncate_at_char_boundary;
 
 pub const UNKNOWN: &str = "<unknown>";
 pub const UTF_8: &str = "utf-8";
 pub const US_ASCII: &str = "us-ascii";
 
+/// The maximal number of bytes of a str context kept in an `EncodingError`.
+///
+/// Longer str contexts are truncated (see `EncodingError::set_str_context`).
+pub const MAX_STR_CONTEXT_LEN: usize = 128;
+
 /// A general error appearing when encoding failed in some way.
+///
+/// New kinds can be added in any release, to handle specific
+/// kinds match on `code()` or use a wildcard arm.
 #[derive(Copy, Clone, Debug, Fail, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub enum EncodingErrorKind {
 
     #[fail(display = "expected <{}> text encoding {} got ",
@@ -41,6 +52,28 @@ pub enum EncodingErrorKind {
     //UnsupportedEncoding { encoding: &'static str }
 }
 
+impl EncodingErrorKind {
+
+    /// Returns a stable machine-readable code for the error kind.
+    ///
+    /// Unlike the `Display` output the codes are part of the public
+    /// API, i.e. the code of an existing kind will not change and no
+    /// two kinds will have the same code. They are meant to be used
+    /// by clients which are not written in rust. Note that `Other`
+    /// has a single code independent of its `kind` field.
+    pub fn code(&self) -> &'static str {
+        use self::EncodingErrorKind::*;
+        match *self {
+            InvalidTextEncoding { .. } => "ENC_INVALID_TEXT_ENCODING",
+            HardLineLengthLimitBreached => "ENC_HARD_LINE_LENGTH_LIMIT",
+            NotEncodable { .. } => "ENC_NOT_ENCODABLE",
+            Malformed => "ENC_MALFORMED",
+            AccessingMailBodyFailed => "ENC_BODY_ACCESS_FAILED",
+            Other { .. } => "ENC_OTHER"
+        }
+    }
+}
+
 
 /// A general error appearing when encoding failed in some way.
 ///
@@ -48,16 +81,18 @@ pub enum EncodingErrorKind {
 /// of contextual information including: The place the error
 /// happened in (`Header { name }`,`Body`), a string representing
 /// the context when it happens (e.g. the word which could not be encoded),
-/// and the mail type.
+/// the byte offset of the problem in the input (if known) and the mail type.
 #[derive(Debug)]
 pub struct EncodingError {
     inner: Context<EncodingErrorKind>,
     mail_type: Option<MailType>,
     str_context: Option<String>,
+    input_offset: Option<usize>,
     place: Option<Place>
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Place {
     Header { name: &'static str },
     Body
@@ -74,16 +109,57 @@ impl EncodingError {
         self.mail_type
     }
 
+    /// Returns the machine-readable code of the error kind (see `EncodingErrorKind::code`).
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+
+    /// Returns the place the error appeared in (if known).
+    pub fn place(&self) -> Option<&Place> {
+        self.place.as_ref()
+    }
+
     /// Returns the str_context associated with the error.
     pub fn str_context(&self) -> Option<&str> {
         self.str_context.as_ref().map(|s| &**s)
     }
 
     /// Sets the str context.
+    ///
+    /// If the context is longer than `MAX_STR_CONTEXT_LEN` bytes it is
+    /// truncated (at a char boundary) and `"…"` as well as the original
+    /// length (in bytes) is appended, so that errors about huge inputs
+    /// don't contain the whole input.
     pub fn set_str_context<I>(&mut self, ctx: I)
         where I: Into<String>
     {
-        self.str_context = Some(ctx.into());
+        let mut ctx = ctx.into();
+        let total_len = ctx.len();
+        if total_len > MAX_STR_CONTEXT_LEN {
+            let cut_idx = truncate_at_char_boundary(&ctx, MAX_STR_CONTEXT_LEN).len();
+            ctx.truncate(cut_idx);
+            ctx.push_str(&format!("… ({} bytes total)", total_len));
+        }
+        self.str_context = Some(ctx);
+    }
+
+    /// Returns the byte offset of the first offending char in the input (if known).
+    ///
+    /// The offset is relative to the input the error is about, which is not
+    /// necessary the same as the str context.
+    pub fn input_offset(&self) -> Option<usize> {
+        self.input_offset
+    }
+
+    /// Sets the byte offset of the first offending char in the input.
+    pub fn set_input_offset(&mut self, offset: usize) {
+        self.input_offset = Some(offset);
+    }
+
+    /// Returns a version of self with the given input offset.
+    pub fn with_input_offset(mut self, offset: usize) -> Self {
+        self.set_input_offset(offset);
+        self
     }
 
     /// Returns a version of self which has a str context like the given one.
@@ -127,11 +203,19 @@ impl From<Context<EncodingErrorKind>> for EncodingError {
             inner,
             mail_type: None,
             str_context: None,
+            input_offset: None,
             place: None
         }
     }
 }
 
+/// Allows using `?` with infallible conversions, e.g. from `Into`-based `TryInto` impls.
+impl From<Infallible> for EncodingError {
+    fn from(never: Infallible) -> Self {
+        match never {}
+    }
+}
+
 impl From<(EncodingErrorKind, MailType)> for EncodingError {
     fn from((ctx, mail_type): (EncodingErrorKind, MailType)) -> Self {
         EncodingError::from((Context::new(ctx), mail_type))
@@ -144,11 +228,51 @@ impl From<(Context<EncodingErrorKind>, MailType)> for EncodingError {
             inner,
             mail_type: Some(mail_type),
             str_context: None,
+            input_offset: None,
             place: None
         }
     }
 }
 
+/// A plain representation of an `EncodingError` for reporting it to non rust clients.
+///
+/// It's created through `EncodingError::report`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct ErrorReport {
+    /// the stable code of the error kind (see `EncodingErrorKind::code`)
+    pub code: &'static str,
+    /// the (not stable) display message of the error
+    pub message: String,
+    /// the name of the header the error appeared in (if known)
+    pub header: Option<&'static str>,
+    /// the byte offset of the problem in the input (if known)
+    pub position: Option<usize>
+}
+
+impl EncodingError {
+
+    /// Creates an `ErrorReport` for this error.
+    pub fn report(&self) -> ErrorReport {
+        let header = match self.place {
+            Some(Place::Header { name }) => Some(name),
+            _ => None
+        };
+        ErrorReport {
+            code: self.code(),
+            message: self.to_string(),
+            header,
+            position: self.input_offset
+        }
+    }
+}
+
+impl<'a> From<&'a EncodingError> for ErrorReport {
+    fn from(err: &'a EncodingError) -> Self {
+        err.report()
+    }
+}
+
 impl Fail for EncodingError {
 
     fn cause(&self) -> Option<&Fail> {
@@ -217,6 +341,73 @@ macro_rules! ec_bail {
 
 #[cfg(test)]
 mod test {
+    use super::*;
+
+    #[test]
+    fn short_str_context_is_kept() {
+        let err = EncodingError::from(EncodingErrorKind::Malformed)
+            .with_str_context("short");
+        assert_eq!(err.str_context(), Some("short"));
+    }
+
+    #[test]
+    fn long_str_context_is_truncated() {
+        let input = "ä".repeat(1_000_000);
+        let err = EncodingError::from(EncodingErrorKind::Malformed)
+            .with_str_context(input);
+        let expected = format!("{}… (2000000 bytes total)", "ä".repeat(MAX_STR_CONTEXT_LEN / 2));
+        assert_eq!(err.str_context(), Some(&*expected));
+    }
+
+    #[test]
+    fn input_offset() {
+        let err = EncodingError::from(EncodingErrorKind::Malformed);
+        assert_eq!(err.input_offset(), None);
+        let err = err.with_input_offset(3);
+        assert_eq!(err.input_offset(), Some(3));
+    }
+
+    /// One instance of each kind, extend it when adding a new kind.
+    fn all_kinds() -> Vec<EncodingErrorKind> {
+        use self::EncodingErrorKind::*;
+        let kinds = vec![
+            InvalidTextEncoding { expected_encoding: US_ASCII, got_encoding: UTF_8 },
+            HardLineLengthLimitBreached,
+            NotEncodable { encoding: US_ASCII },
+            Malformed,
+            AccessingMailBodyFailed,
+            Other { kind: "other" }
+        ];
+        // fails to compile if a kind is added but not listed above
+        for kind in kinds.iter() {
+            match *kind {
+                InvalidTextEncoding { .. } | HardLineLengthLimitBreached
+                | NotEncodable { .. } | Malformed | AccessingMailBodyFailed
+                | Other { .. } => {}
+            }
+        }
+        kinds
+    }
+
+    #[test]
+    fn error_codes_are_unique() {
+        use std::collections::HashSet;
+        let kinds = all_kinds();
+        let codes = kinds.iter().map(|kind| kind.code()).collect::<HashSet<_>>();
+        assert_eq!(codes.len(), kinds.len());
+    }
+
+    #[test]
+    fn error_report() {
+        let err = EncodingError::from((EncodingErrorKind::Malformed, ::MailType::Ascii))
+            .with_place_or_else(|| Some(Place::Header { name: "Subject" }))
+            .with_input_offset(4);
+        let report = err.report();
+        assert_eq!(report.code, "ENC_MALFORMED");
+        assert_eq!(report.message, err.to_string());
+        assert_eq!(report.header, Some("Subject"));
+        assert_eq!(report.position, Some(4));
+    }
 
     #[test]
     fn bail_compiles_v1() {
@@ -190,37 +190,36 @@ pub fn is_token(s: &str) -> bool {
     0 < s.len() && s.chars().all(is_token_char)
 }
 
-//
-//pub fn is_dot_atom_text( text: &str, mt: MailType ) -> bool {
-//    use nom::IResult;
-//    use self::parse::recognize_dot_atom_text;
-//
-//    let res = tuple!( text,
-//        call!( recognize_dot_atom_text, mt ),
-//        eof!()
-//    );
-//
-//    match res {
-//        IResult::Done(_, _) => true,
-//        _ => false
-//    }
-//}
-
-//pub mod parse {
-//    use nom::IResult;
-//    use super::{ is_atext, MailType };
-//
-//    pub fn recognize_dot_atom_text( input: &str, mt: MailType ) -> IResult<&str, &str> {
-//        recognize!( input, tuple!(
-//            take_while1!( call!( is_atext, mt ) ),
-//            many0!( tuple!(
-//                char!( "." ),
-//                take_while1!( call!( is_atext, mt ) )
-//            ) )
-//        ) )
-//    }
-//
-//}
+/// Returns the byte offset of the first char which is not a token char.
+///
+/// Note that this returns `None` for an empty string, even though an
+/// empty string is not a token.
+pub fn find_non_token_char(s: &str) -> Option<usize> {
+    s.char_indices()
+        .find(|&(_, ch)| !is_token_char(ch))
+        .map(|(idx, _)| idx)
+}
+
+/// Check if a string is dot-atom-text (based on RFC 5322).
+///
+/// I.e. it's one or more `atext` sequences separated by single dots
+/// (so it can neither start nor end with a dot).
+pub fn is_dot_atom_text(text: &str, mt: MailType) -> bool {
+    !text.is_empty() && text.split('.')
+        .all(|part| !part.is_empty() && part.chars().all(|ch| is_atext(ch, mt)))
+}
+
+/// Check if a string is a no-fold-literal (based on RFC 5322).
+///
+/// I.e. `"[" *dtext "]"`, which can be used as `id-right`
+/// of a message id.
+pub fn is_no_fold_literal(text: &str, mt: MailType) -> bool {
+    text.len() >= 2
+        && text.starts_with('[')
+        && text.ends_with(']')
+        && text[1..text.len()-1].chars().all(|ch| is_dtext(ch, mt))
+}
+
 //TODO this should be some where else I think
 // (but it is used by `1. codec`, `2. components` )
 /// Grammar parts for encoded words (based on RFC 2047).
@@ -419,5 +418,36 @@ mod test {
     fn is_toke_empty() {
         assert_eq!(false, is_token(""));
     }
+
+    #[test]
+    fn find_non_token_char_offsets() {
+        assert_eq!(find_non_token_char("abc"), None);
+        assert_eq!(find_non_token_char("ab c"), Some(2));
+        assert_eq!(find_non_token_char("äb"), Some(0));
+    }
+
+    #[test]
+    fn dot_atom_text() {
+        assert!(is_dot_atom_text("a.b.c", MailType::Ascii));
+        assert!(is_dot_atom_text("a+b", MailType::Ascii));
+        assert_not!(is_dot_atom_text("", MailType::Ascii));
+        assert_not!(is_dot_atom_text(".a", MailType::Ascii));
+        assert_not!(is_dot_atom_text("a.", MailType::Ascii));
+        assert_not!(is_dot_atom_text("a..b", MailType::Ascii));
+        assert_not!(is_dot_atom_text("a b", MailType::Ascii));
+        assert_not!(is_dot_atom_text("a@b", MailType::Ascii));
+        assert_not!(is_dot_atom_text("tüxt", MailType::Ascii));
+        assert!(is_dot_atom_text("tüxt", MailType::Internationalized));
+    }
+
+    #[test]
+    fn no_fold_literal() {
+        assert!(is_no_fold_literal("[127.0.0.1]", MailType::Ascii));
+        assert!(is_no_fold_literal("[]", MailType::Ascii));
+        assert_not!(is_no_fold_literal("[", MailType::Ascii));
+        assert_not!(is_no_fold_literal("127.0.0.1", MailType::Ascii));
+        assert_not!(is_no_fold_literal("[a]b]", MailType::Ascii));
+        assert_not!(is_no_fold_literal("[a b]", MailType::Ascii));
+    }
 }
 
@@ -14,6 +14,8 @@ extern crate quoted_string;
 extern crate media_type_impl_utils;
 extern crate percent_encoding;
 extern crate vec1;
+#[cfg(feature="unicode-segmentation")]
+extern crate unicode_segmentation;
 
 //NOTE: this would be worth it's own independent crate for utility macros
 #[macro_use]
@@ -21,6 +23,9 @@ mod macros;
 #[macro_use]
 pub mod utils;
 mod mail_type;
+mod charset;
+#[macro_use]
+mod token;
 #[macro_use]
 pub mod error;
 pub mod grammar;
@@ -31,6 +36,8 @@ pub mod encoder;
 pub mod bind;
 
 pub use self::mail_type::*;
+pub use self::charset::*;
+pub use self::token::*;
 
 #[cfg(all(test, not(feature="traceing")))]
 compile_error! { "testing needs feature `traceing` to be enabled" }
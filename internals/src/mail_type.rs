@@ -1,5 +1,6 @@
 /// Specifies what kind of mail we want to create.
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum MailType {
     /// A 7-bit us-ascii mail.
     Ascii,
@@ -27,6 +28,13 @@ pub enum MailType {
     Internationalized
 }
 
+/// Defaults to `Ascii`, as it's compatible with all mail servers.
+impl Default for MailType {
+    fn default() -> Self {
+        MailType::Ascii
+    }
+}
+
 impl MailType {
 
     /// Returns true if the self is equal to `Internationalized`
@@ -0,0 +1,232 @@
+use std::convert::TryFrom;
+use std::fmt::{self, Display};
+use std::ops::Deref;
+
+use soft_ascii_string::{SoftAsciiStr, SoftAsciiString};
+
+use ::error::{EncodingError, EncodingErrorKind};
+use grammar::{is_token, find_non_token_char};
+
+/// Creates a `&'static TokenStr` from a literal, checking it at compile time.
+///
+/// ```
+/// # #[macro_use] extern crate mail_internals;
+/// # fn main() {
+/// let name = token!("charset");
+/// assert_eq!(name.as_str(), "charset");
+/// # }
+/// ```
+///
+/// Using a literal which is not a token fails to compile.
+#[macro_export]
+macro_rules! token {
+    ($token:literal) => ({
+        const TOKEN: &'static $crate::TokenStr = {
+            assert!($crate::TokenStr::is_valid($token), concat!("not a token: ", $token));
+            $crate::TokenStr::from_unchecked($token)
+        };
+        TOKEN
+    });
+}
+
+/// A borrowed str which is known to be a token (based on RFC 2045).
+///
+/// Tokens are e.g. used as mime parameter names, functions accepting
+/// `TryInto<&TokenStr>` accept both `&str`'s (which are then checked)
+/// and pre-validated `&TokenStr`/`&Token`'s (which are not checked again).
+#[derive(Debug, Hash, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct TokenStr(str);
+
+impl TokenStr {
+
+    /// Returns the str as `TokenStr` if it's a token.
+    ///
+    /// # Error
+    ///
+    /// Fails with `Malformed` if the str is not a token, the input
+    /// offset of the error points to the first non token char.
+    pub fn new(s: &str) -> Result<&TokenStr, EncodingError> {
+        if !is_token(s) {
+            let mut err = EncodingError::from(EncodingErrorKind::Malformed);
+            err.set_str_context(s);
+            err.set_input_offset(find_non_token_char(s).unwrap_or(0));
+            return Err(err);
+        }
+        Ok(TokenStr::from_unchecked(s))
+    }
+
+    /// Returns the str as `TokenStr` without checking if it's a token.
+    ///
+    /// This is not unsafe, but using it with a str which is not a token
+    /// can lead to invalid mails. For literals use the `token!` macro
+    /// instead, which checks the literal at compile time.
+    pub const fn from_unchecked(s: &str) -> &TokenStr {
+        //SAFE: TokenStr is a repr(transparent) wrapper around str
+        unsafe { &*(s as *const str as *const TokenStr) }
+    }
+
+    /// Like `grammar::is_token` but usable in const contexts.
+    pub const fn is_valid(s: &str) -> bool {
+        let bytes = s.as_bytes();
+        if bytes.is_empty() {
+            return false;
+        }
+        let mut idx = 0;
+        while idx < bytes.len() {
+            match bytes[idx] {
+                b'(' | b')' | b'<' | b'>' | b'@' | b',' | b';' | b':' |
+                b'\\' | b'"' | b'/' | b'[' | b']' | b'?' | b'=' => return false,
+                b'!'...b'\x7f' => {},
+                _ => return false
+            }
+            idx += 1;
+        }
+        true
+    }
+
+    /// Returns the token as str.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the token as `SoftAsciiStr`.
+    pub fn as_soft_str(&self) -> &SoftAsciiStr {
+        //SAFE: tokens are always us-ascii
+        SoftAsciiStr::from_unchecked(&self.0)
+    }
+
+    /// Returns a owned copy of the token.
+    pub fn to_token(&self) -> Token {
+        Token(self.as_soft_str().to_owned())
+    }
+}
+
+impl AsRef<str> for TokenStr {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Display for TokenStr {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        fter.write_str(self.as_str())
+    }
+}
+
+impl<'a> TryFrom<&'a str> for &'a TokenStr {
+    type Error = EncodingError;
+
+    fn try_from(s: &'a str) -> Result<Self, Self::Error> {
+        TokenStr::new(s)
+    }
+}
+
+/// A owned string which is known to be a token (based on RFC 2045).
+///
+/// See `TokenStr`.
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct Token(SoftAsciiString);
+
+impl Token {
+
+    /// Creates a token from the given string if it's a token.
+    ///
+    /// # Error
+    ///
+    /// Fails in the same way as `TokenStr::new`.
+    pub fn new<S>(s: S) -> Result<Token, EncodingError>
+        where S: Into<String>
+    {
+        let s = s.into();
+        TokenStr::new(&s)?;
+        //SAFE: tokens are always us-ascii
+        Ok(Token(SoftAsciiString::from_unchecked(s)))
+    }
+
+    /// Returns the token as `TokenStr` (which is also available through `Deref`).
+    pub fn as_token_str(&self) -> &TokenStr {
+        TokenStr::from_unchecked(self.0.as_str())
+    }
+}
+
+impl Deref for Token {
+    type Target = TokenStr;
+
+    fn deref(&self) -> &TokenStr {
+        self.as_token_str()
+    }
+}
+
+impl AsRef<str> for Token {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Display for Token {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        fter.write_str(self.as_str())
+    }
+}
+
+impl<'a> From<&'a Token> for &'a TokenStr {
+    fn from(token: &'a Token) -> Self {
+        token.as_token_str()
+    }
+}
+
+impl<'a> From<&'a TokenStr> for Token {
+    fn from(token: &'a TokenStr) -> Self {
+        token.to_token()
+    }
+}
+
+impl<'a> TryFrom<&'a str> for Token {
+    type Error = EncodingError;
+
+    fn try_from(s: &'a str) -> Result<Self, Self::Error> {
+        Token::new(s)
+    }
+}
+
+impl TryFrom<String> for Token {
+    type Error = EncodingError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Token::new(s)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+    use super::{Token, TokenStr};
+
+    #[test]
+    fn checked_constructors() {
+        assert_ok!(TokenStr::new("charset"));
+        assert_ok!(Token::new("x-custom"));
+        let err = assert_err!(TokenStr::new("file name"));
+        assert_eq!(err.input_offset(), Some(4));
+        assert_err!(Token::new(""));
+        assert_err!(<&TokenStr>::try_from("a=b"));
+    }
+
+    #[test]
+    fn is_valid_matches_is_token() {
+        use grammar::is_token;
+        let inputs = &["", "a", "charset", "a b", "a=b", "ä", "a\x7f", "a\t", "x-y.z"];
+        for input in inputs.iter() {
+            assert_eq!(TokenStr::is_valid(input), is_token(input), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn token_macro() {
+        let name: &'static TokenStr = token!("boundary");
+        assert_eq!(name.as_str(), "boundary");
+        assert_eq!(name.to_token(), assert_ok!(Token::new("boundary")));
+    }
+}
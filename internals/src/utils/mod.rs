@@ -8,6 +8,7 @@ use std::cell::RefCell;
 use std::mem;
 use std::fmt::{self, Debug};
 
+pub mod text;
 
 /// Helper for implementing debug for an iterable think where the think on itself is irrelevant.
 pub struct DebugIterableOpaque<I> {
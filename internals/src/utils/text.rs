@@ -0,0 +1,122 @@
+//! Utilities for truncating text without splitting UTF-8 sequences.
+//!
+//! All functions take the maximal length in bytes and never return
+//! more than that many bytes (ellipsis included) and never split
+//! a UTF-8 sequence.
+use std::borrow::Cow;
+
+#[cfg(feature="unicode-segmentation")]
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The ellipsis used by the `ellipsize*` functions.
+pub const ELLIPSIS: &str = "…";
+
+/// Truncates the str to at most `max_bytes` bytes, cutting at a char boundary.
+pub fn truncate_at_char_boundary(s: &str, max_bytes: usize) -> &str {
+    if s.len() <= max_bytes {
+        return s;
+    }
+    let mut idx = max_bytes;
+    // a char has at most 4 bytes, so this loops at most 3 times
+    while !s.is_char_boundary(idx) {
+        idx -= 1;
+    }
+    &s[..idx]
+}
+
+/// Truncates the str to at most `max_bytes` bytes, cutting at a grapheme cluster boundary.
+///
+/// This can return an empty string even if `max_bytes` is not `0`,
+/// e.g. if the first grapheme cluster is longer then `max_bytes`.
+#[cfg(feature="unicode-segmentation")]
+pub fn truncate_at_grapheme(s: &str, max_bytes: usize) -> &str {
+    if s.len() <= max_bytes {
+        return s;
+    }
+    let mut end_idx = 0;
+    for (idx, grapheme) in s.grapheme_indices(true) {
+        let grapheme_end = idx + grapheme.len();
+        if grapheme_end > max_bytes {
+            break;
+        }
+        end_idx = grapheme_end;
+    }
+    &s[..end_idx]
+}
+
+/// Like `truncate_at_char_boundary` but ends truncated strings with `ELLIPSIS`.
+///
+/// If `max_bytes` is smaller than the ellipsis the str is truncated without it.
+pub fn ellipsize_at_char_boundary(s: &str, max_bytes: usize) -> Cow<str> {
+    ellipsize_with(s, max_bytes, truncate_at_char_boundary)
+}
+
+/// Like `truncate_at_grapheme` but ends truncated strings with `ELLIPSIS`.
+///
+/// If `max_bytes` is smaller than the ellipsis the str is truncated without it.
+#[cfg(feature="unicode-segmentation")]
+pub fn ellipsize_at_grapheme(s: &str, max_bytes: usize) -> Cow<str> {
+    ellipsize_with(s, max_bytes, truncate_at_grapheme)
+}
+
+fn ellipsize_with<FN>(s: &str, max_bytes: usize, truncate: FN) -> Cow<str>
+    where FN: for<'a> Fn(&'a str, usize) -> &'a str
+{
+    if s.len() <= max_bytes {
+        Cow::Borrowed(s)
+    } else if max_bytes < ELLIPSIS.len() {
+        Cow::Borrowed(truncate(s, max_bytes))
+    } else {
+        let mut out = truncate(s, max_bytes - ELLIPSIS.len()).to_owned();
+        out.push_str(ELLIPSIS);
+        Cow::Owned(out)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MIXED: &str = "aä↓😀b\u{301}c";
+
+    #[test]
+    fn short_strings_are_not_changed() {
+        assert_eq!(truncate_at_char_boundary("abc", 3), "abc");
+        assert_eq!(ellipsize_at_char_boundary("abc", 3), "abc");
+    }
+
+    #[test]
+    fn truncate_never_splits_chars() {
+        assert_eq!(truncate_at_char_boundary("aä", 2), "a");
+        assert_eq!(truncate_at_char_boundary("😀", 3), "");
+        for max_bytes in 0..MIXED.len() + 2 {
+            let out = truncate_at_char_boundary(MIXED, max_bytes);
+            assert!(out.len() <= max_bytes);
+            assert!(MIXED.starts_with(out));
+        }
+    }
+
+    #[test]
+    fn ellipsize_stays_in_limit() {
+        assert_eq!(ellipsize_at_char_boundary("abcdef", 5), "ab…");
+        assert_eq!(ellipsize_at_char_boundary("abcdef", 2), "ab");
+        for max_bytes in 0..MIXED.len() + 2 {
+            let out = ellipsize_at_char_boundary(MIXED, max_bytes);
+            assert!(out.len() <= max_bytes);
+        }
+    }
+
+    #[cfg(feature="unicode-segmentation")]
+    #[test]
+    fn truncate_never_splits_graphemes() {
+        // "b\u{301}" is one grapheme cluster of 3 bytes
+        let b_idx = MIXED.find('b').unwrap();
+        assert_eq!(truncate_at_grapheme(MIXED, b_idx + 1), &MIXED[..b_idx]);
+        assert_eq!(truncate_at_grapheme(MIXED, b_idx + 3), &MIXED[..b_idx + 3]);
+        for max_bytes in 0..MIXED.len() + 2 {
+            assert!(truncate_at_grapheme(MIXED, max_bytes).len() <= max_bytes);
+            assert!(ellipsize_at_grapheme(MIXED, max_bytes).len() <= max_bytes);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Checks that common downstream usage patterns only rely on the
+//! stable parts of the public API (i.e. they keep compiling when
+//! variants or options are added).
+//!
+//! The types are made extensible using `#[non_exhaustive]`, which needs
+//! Rust 1.40 (see the minimum supported Rust version in the README).
+extern crate mail_internals;
+extern crate soft_ascii_string;
+
+use soft_ascii_string::SoftAsciiStr;
+
+use mail_internals::MailType;
+use mail_internals::error::{EncodingError, EncodingErrorKind};
+use mail_internals::encoder::{EncodingBuffer, FoldingMode, LineEnding};
+use mail_internals::bind::mime::{
+    create_encoded_mime_parameter_with_options,
+    ParamEncodingOptions, ParamEncodingPolicy
+};
+
+fn describe(err: &EncodingError) -> &'static str {
+    match err.code() {
+        "ENC_HARD_LINE_LENGTH_LIMIT" => "line too long",
+        "ENC_MALFORMED" => "malformed",
+        _ => "other"
+    }
+}
+
+#[test]
+fn errors_are_matched_by_code() {
+    let err = create_encoded_mime_parameter_with_options(
+        "file name", "x", MailType::Ascii, ParamEncodingOptions::new()
+    ).unwrap_err();
+    assert_eq!(describe(&err), "malformed");
+
+    match err.kind() {
+        EncodingErrorKind::Malformed => {},
+        _ => panic!("unexpected error kind")
+    }
+}
+
+#[test]
+fn options_are_created_through_builders() {
+    let options = ParamEncodingOptions::default()
+        .with_policy(ParamEncodingPolicy::PreferExtended)
+        .with_legacy_utf8_label(true);
+    let params = create_encoded_mime_parameter_with_options(
+        "filename", "a b", MailType::Ascii, options
+    ).unwrap();
+    assert_eq!(params[0].value, "utf8''a%20b");
+}
+
+#[test]
+fn config_enums_have_defaults() {
+    assert_eq!(MailType::default(), MailType::Ascii);
+    assert_eq!(LineEnding::default(), LineEnding::CrLf);
+    assert_eq!(FoldingMode::default(), FoldingMode::Fold);
+
+    let mut encoder = EncodingBuffer::new(MailType::default());
+    encoder.write_header_line(|handle| {
+        handle.write_str(SoftAsciiStr::from_unchecked("X-A: b"))
+    }).unwrap();
+    assert_eq!(encoder.as_slice(), b"X-A: b\r\n");
+
+    let policy = match MailType::default() {
+        MailType::Internationalized => ParamEncodingPolicy::PreferQuoted,
+        _ => ParamEncodingPolicy::Both
+    };
+    assert_eq!(policy, ParamEncodingPolicy::Both);
+}